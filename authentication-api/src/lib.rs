@@ -0,0 +1,16 @@
+// Request and response bodies of the backend HTTP API, shared by the worker, the wasm bindings and
+// the native client. OPAQUE messages are base64 strings made by `authentication-rs-lib`, the
+// `*/start` endpoints answer with the server message alone as plain text.
+//
+// The TypeScript definitions in `authentication-frontend/src/lib/api` are generated from these
+// types with `cargo test --features typescript`, commit them with any change here.
+
+pub mod v1;
+
+pub use v1::*;
+
+// Version of the types re-exported at the crate root. Clients send it in the `API_VERSION_HEADER`
+// header and the backend rejects versions it doesn't know, a request without it is taken as v1.
+// Changing a type in a way older clients can't handle means adding a new version module.
+pub const API_VERSION: u16 = 1;
+pub const API_VERSION_HEADER: &str = "Api-Version";
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "typescript")]
+use ts_rs::TS;
+
+// `/register/start` and `/register/end`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct RegistrationRequest {
+    pub username: String,
+    pub mail: String,
+    pub request: String,
+}
+
+// Stable identifier of an `ErrorResponse`, clients should react to it rather than to the message
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    InvalidFields,
+    InvalidUsername,
+    UnsupportedApiVersion,
+    UnsupportedCipherSuite,
+    UserAlreadyRegistered,
+    RegistrationPendingEmailConfirmation,
+    TooManyAttempts,
+    NoRegistrationState,
+    NoLoginState,
+    PasswordFileUpToDate,
+    InvalidResetToken,
+    InvalidConfirmationKey,
+    UnknownUser,
+    EmailNotVerified,
+    EmailAlreadyVerified,
+    EmailAlreadyInUse,
+    AccountLocked,
+    InvalidKeyConfirmation,
+    Unauthorized,
+    SessionNotFound,
+    ServerKeyUnavailable,
+    Internal,
+}
+
+// Body of every error answer. The message is English and meant for logs, `fields` maps the
+// fields of an `InvalidFields` request to what is wrong with them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct ErrorResponse {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    #[cfg_attr(feature = "typescript", ts(optional, as = "Option<HashMap<String, String>>"))]
+    pub fields: HashMap<String, String>,
+}
+
+// `/login/start`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct LoginRequest {
+    pub username: String,
+    pub request: String,
+}
+
+// `/login/end`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct LoginFinishRequest {
+    pub username: String,
+    pub request: String,
+    pub confirmation: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct LoginFinishResponse {
+    pub confirmation: String,
+    // the password file uses an older server key, cipher suite or Argon2 profile, the client
+    // should register it again through `/password/upgrade`
+    #[serde(default)]
+    pub upgrade: bool,
+}
+
+// `/password/change/*` and `/password/upgrade/*`, the user is the one of the session
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct PasswordChangeRequest {
+    pub request: String,
+}
+
+// `/password/reset/request`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct PasswordResetRequest {
+    pub username: String,
+}
+
+// `/password/reset/start` and `/password/reset/end`, `token` comes from the reset email
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct PasswordResetRegistrationRequest {
+    pub username: String,
+    pub token: String,
+    pub request: String,
+}
+
+// `/register/confirm/resend`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct EmailConfirmationResendRequest {
+    pub username: String,
+}
+
+// `/account/email`, the user is the one of the session
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct EmailChangeRequest {
+    pub mail: String,
+}
+
+// `GET /session`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct SessionResponse {
+    pub username: String,
+    pub mail: String,
+}
+
+// Items of `GET /sessions`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct SessionListItem {
+    pub id: String,
+    // milliseconds since the epoch
+    #[cfg_attr(feature = "typescript", ts(type = "number"))]
+    pub created: u64,
+    pub current: bool,
+}
+
+// `/admin/migrations/sessions` and `/admin/migrations/mail-index`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct MigrationResponse {
+    #[cfg_attr(feature = "typescript", ts(type = "number"))]
+    pub migrated: usize,
+}
+
+// `/admin/migrations/schema`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "typescript", derive(TS), ts(export))]
+pub struct SchemaMigrationResponse {
+    pub version: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ErrorCode, ErrorResponse, LoginFinishResponse};
+
+    #[test]
+    fn upgrade_defaults_to_false() {
+        let response: LoginFinishResponse = serde_json::from_str(r#"{"confirmation":"abc"}"#).unwrap();
+        assert_eq!(response, LoginFinishResponse { confirmation: "abc".to_string(), upgrade: false });
+    }
+
+    #[test]
+    fn error_codes_are_snake_case() {
+        let error = ErrorResponse { code: ErrorCode::TooManyAttempts, message: "Too many attempts".to_string(), fields: Default::default() };
+        assert_eq!(serde_json::to_string(&error).unwrap(), r#"{"code":"too_many_attempts","message":"Too many attempts"}"#);
+    }
+}
@@ -1,40 +1,118 @@
 use opaque_ke::rand::{rngs::OsRng, RngCore};
-use serde_json::json;
 use worker::Result;
-pub async fn send(username: &str, _email: &str, emailer_key: &str) -> Result<String> {
-     let mut email_verification_key = [0u8; 32];
-     OsRng.fill_bytes(&mut email_verification_key);
-     let email_verification_key = base64::encode_config(&email_verification_key, base64::URL_SAFE);
-
-     let mut headers = worker::Headers::new();
-     headers.append("Content-Type", "application/json")?;
-     headers.append("Accept", "application/json")?;
-     headers.append("api-key", emailer_key)?;
-     let body = json!({
-          "sender": {
-               "name": "Tomé Vardasca",
-               "email": "tome@vardas.ca"
-          },
-          "to": [
-               {
-                    "email": "tome@vardas.ca",
-                    "name": "Tomé Vardasca"
-               }
-          ],
-          "textContent": format!("Please confirm your email address by clicking on the link http://127.0.0.1:8787/register/confirm/{}?k={}", &username, &email_verification_key),
-          "htmlContent": format!("<!DOCTYPE html> <html> <body> <h1>Confirm you email</h1> <p>Please confirm your email address by clicking on the link below</p> <a href=\"http://127.0.0.1:8787/register/confirm/{username}?k={key}\">http://127.0.0.1:8787/register/confirm/{username}?k={key}</a> </body> </html>", username = &username, key = &email_verification_key),
-          "subject": "Login Email confirmation"
-     });
-
-     let mut req_init = worker::RequestInit::new();
-     req_init.with_method(worker::Method::Post).with_headers(headers).with_body(Some(worker::wasm_bindgen::JsValue::from_str(&body.to_string())));
-
-     let req = worker::Request::new_with_init("https://api.sendinblue.com/v3/smtp/email", &req_init)?;
-
-     let fetch = worker::Fetch::Request(req);
-     let mut response = fetch.send().await?;
-     let text = response.text().await?;
-
-     worker::console_log!("email verification : [ status: '{}', text: '{}' ]", response.status_code(), text);
-     Ok(email_verification_key)
-}
\ No newline at end of file
+
+use crate::{data::{email_change::{EMAIL_CHANGE_TTL, EMAIL_REVERT_TTL}, password_reset::PASSWORD_RESET_TTL}, email::{Mailer, Template}};
+
+pub fn generate_key() -> String {
+     let mut key = [0u8; 32];
+     OsRng.fill_bytes(&mut key);
+     base64::encode_config(key, base64::URL_SAFE)
+}
+
+// `ttl` is how long the key is valid, in seconds
+pub async fn send(mailer: &Mailer<'_>, username: &str, email: &str, email_verification_key: &str, ttl: u64) -> Result<()> {
+     let link = format!("{}/register/confirm/{}?k={}", mailer.public_url, username, email_verification_key);
+     let expiry = ttl.div_ceil(60 * 60).to_string();
+     mailer.send(email, Template::Confirmation, &[("username", username), ("link", &link), ("expiry", &expiry)]).await
+}
+
+// `password_reset_token` is made with `generate_key`
+pub async fn send_password_reset(mailer: &Mailer<'_>, username: &str, email: &str, password_reset_token: &str) -> Result<()> {
+     let link = format!("{}/reset?u={}&t={}", mailer.frontend_url, username, password_reset_token);
+     let expiry = (PASSWORD_RESET_TTL / 60).to_string();
+     mailer.send(email, Template::PasswordReset, &[("username", username), ("link", &link), ("expiry", &expiry)]).await
+}
+
+// `device` describes the device that logged in, its user agent
+pub async fn send_new_device_login(mailer: &Mailer<'_>, username: &str, email: &str, device: &str) -> Result<()> {
+     let link = format!("{}/password", mailer.frontend_url);
+     mailer.send(email, Template::NewDeviceLogin, &[("username", username), ("device", device), ("link", &link)]).await
+}
+
+// After a password change or upgrade, the link is for a reset if it wasn't the user
+pub async fn send_password_changed(mailer: &Mailer<'_>, username: &str, email: &str) -> Result<()> {
+     let link = format!("{}/reset?u={}", mailer.frontend_url, username);
+     mailer.send(email, Template::PasswordChanged, &[("username", username), ("link", &link)]).await
+}
+
+// Sent to the new address, `new_email` only replaces the current one once the link is opened.
+// `key` is made with `generate_key`.
+pub async fn send_email_change(mailer: &Mailer<'_>, username: &str, new_email: &str, key: &str) -> Result<()> {
+     let link = format!("{}/account/email/confirm/{}?k={}", mailer.public_url, username, key);
+     let expiry = (EMAIL_CHANGE_TTL / (60 * 60)).to_string();
+     mailer.send(new_email, Template::EmailChangeConfirmation, &[("username", username), ("mail", new_email), ("link", &link), ("expiry", &expiry)]).await
+}
+
+pub async fn send_email_change_notice(mailer: &Mailer<'_>, username: &str, email: &str, new_email: &str) -> Result<()> {
+     let link = format!("{}/password", mailer.frontend_url);
+     mailer.send(email, Template::EmailChangeNotice, &[("username", username), ("mail", new_email), ("link", &link)]).await
+}
+
+// Sent to the old address once the change is done, the link puts it back
+pub async fn send_email_change_revert(mailer: &Mailer<'_>, username: &str, email: &str, new_email: &str, key: &str) -> Result<()> {
+     let link = format!("{}/account/email/revert/{}?k={}", mailer.public_url, username, key);
+     let expiry = (EMAIL_REVERT_TTL / (24 * 60 * 60)).to_string();
+     mailer.send(email, Template::EmailChangeRevert, &[("username", username), ("mail", new_email), ("link", &link), ("expiry", &expiry)]).await
+}
+
+#[cfg(test)]
+mod tests {
+     use futures::executor::block_on;
+
+     use super::{generate_key, send, send_email_change, send_email_change_notice, send_password_changed, send_password_reset};
+     use crate::email::{CaptureSender, Mailer};
+
+     fn mailer<'a>(sender: &'a CaptureSender, locale: &'static str) -> Mailer<'a> {
+          Mailer { sender, locale, public_url: "https://auth.example.com".to_string(), frontend_url: "https://example.com".to_string() }
+     }
+
+     #[test]
+     fn confirmation_goes_to_the_user() {
+          let sender = CaptureSender::new();
+          let key = generate_key();
+          block_on(send(&mailer(&sender, "en"), "alice", "alice@example.com", &key, 24 * 60 * 60)).unwrap();
+
+          let sent = sender.sent();
+          assert_eq!(sent.len(), 1);
+          assert_eq!(sent[0].to, "alice@example.com");
+          assert!(sent[0].text.contains(&format!("https://auth.example.com/register/confirm/alice?k={}", key)));
+          assert!(sent[0].text.contains("within 24 hours"));
+     }
+
+     #[test]
+     fn password_reset_uses_the_locale() {
+          let sender = CaptureSender::new();
+          let token = generate_key();
+          block_on(send_password_reset(&mailer(&sender, "pt"), "alice", "alice@example.com", &token)).unwrap();
+
+          let sent = sender.sent();
+          assert_eq!(sent[0].subject, "Redefinir a sua palavra-passe");
+          assert!(sent[0].text.contains(&format!("https://example.com/reset?u=alice&t={}", token)));
+          assert!(sent[0].text.contains("60 minutos"));
+     }
+
+     #[test]
+     fn password_changed_links_to_a_reset() {
+          let sender = CaptureSender::new();
+          block_on(send_password_changed(&mailer(&sender, "en"), "alice", "alice@example.com")).unwrap();
+
+          let sent = sender.sent();
+          assert_eq!(sent[0].to, "alice@example.com");
+          assert!(sent[0].text.contains("https://example.com/reset?u=alice"));
+     }
+
+     #[test]
+     fn email_change_confirms_the_new_address_and_tells_the_old_one() {
+          let sender = CaptureSender::new();
+          let mailer = mailer(&sender, "en");
+          let key = generate_key();
+          block_on(send_email_change(&mailer, "alice", "new@example.com", &key)).unwrap();
+          block_on(send_email_change_notice(&mailer, "alice", "alice@example.com", "new@example.com")).unwrap();
+
+          let sent = sender.sent();
+          assert_eq!(sent[0].to, "new@example.com");
+          assert!(sent[0].text.contains(&format!("https://auth.example.com/account/email/confirm/alice?k={}", key)));
+          assert_eq!(sent[1].to, "alice@example.com");
+          assert!(sent[1].text.contains("new@example.com") && !sent[1].text.contains(&key));
+     }
+}
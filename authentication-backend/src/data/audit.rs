@@ -0,0 +1,16 @@
+use async_trait::async_trait;
+
+use super::{AuthenticationData, backend::KeyValueBackend, durable_object::DurableObjectData};
+
+#[async_trait(?Send)]
+pub trait AuditData {
+    // Records an account event, e.g. a login or a password change. Backends without an audit
+    // log ignore them.
+    async fn record_audit_event(&self, _username: &str, _event: &str) -> worker::Result<()> {
+        Ok(())
+    }
+}
+
+impl<B: KeyValueBackend> AuditData for AuthenticationData<B> {}
+
+impl AuditData for DurableObjectData {}
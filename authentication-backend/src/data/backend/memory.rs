@@ -0,0 +1,105 @@
+use std::{cell::RefCell, collections::BTreeMap};
+
+use async_trait::async_trait;
+
+use super::{KeyValueBackend, ListKey};
+use crate::utils::now_millis;
+
+struct MemoryEntry {
+    value: String,
+    metadata: serde_json::Value,
+    // expiration time in millis
+    expires_at: Option<u64>,
+}
+
+impl MemoryEntry {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+// In memory store for tests and local development, data only lives as long as the backend
+#[derive(Default)]
+pub struct MemoryBackend {
+    entries: RefCell<BTreeMap<String, MemoryEntry>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait(?Send)]
+impl KeyValueBackend for MemoryBackend {
+    async fn get(&self, key: &str) -> worker::Result<Option<String>> {
+        Ok(self.get_with_metadata(key).await?.map(|(value, _)| value))
+    }
+
+    async fn get_with_metadata(&self, key: &str) -> worker::Result<Option<(String, serde_json::Value)>> {
+        let now = now_millis();
+        let entries = self.entries.borrow();
+        Ok(entries.get(key)
+            .filter(|entry| !entry.is_expired(now))
+            .map(|entry| (entry.value.clone(), entry.metadata.clone())))
+    }
+
+    async fn put(&self, key: &str, value: &str, metadata: serde_json::Value, expiration_ttl: Option<u64>) -> worker::Result<()> {
+        let expires_at = expiration_ttl.map(|expiration_ttl| now_millis() + expiration_ttl * 1000);
+        self.entries.borrow_mut().insert(key.to_string(), MemoryEntry { value: value.to_string(), metadata, expires_at });
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> worker::Result<()> {
+        self.entries.borrow_mut().remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> worker::Result<Vec<ListKey>> {
+        let now = now_millis();
+        let entries = self.entries.borrow();
+        Ok(entries.range(prefix.to_string()..)
+            .take_while(|(name, _)| name.starts_with(prefix))
+            .filter(|(_, entry)| !entry.is_expired(now))
+            .map(|(name, entry)| ListKey {
+                name: name.clone(),
+                expiration: entry.expires_at.map(|expires_at| expires_at / 1000),
+                metadata: Some(entry.metadata.clone()),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::{KeyValueBackend, MemoryBackend};
+
+    #[test]
+    fn put_get_list_delete() {
+        block_on(async {
+            let backend = MemoryBackend::new();
+            backend.put("A:1", "one", serde_json::json!(1), None).await.unwrap();
+            backend.put("A:2", "two", serde_json::Value::Null, Some(60)).await.unwrap();
+            backend.put("B:1", "other", serde_json::Value::Null, None).await.unwrap();
+
+            assert_eq!(backend.get_with_metadata("A:1").await.unwrap(), Some(("one".to_string(), serde_json::json!(1))));
+            let keys: Vec<String> = backend.list("A:").await.unwrap().into_iter().map(|key| key.name).collect();
+            assert_eq!(keys, vec!["A:1", "A:2"]);
+
+            backend.delete("A:1").await.unwrap();
+            assert_eq!(backend.get("A:1").await.unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn expired_entries_are_hidden() {
+        block_on(async {
+            let backend = MemoryBackend::new();
+            backend.put("A:1", "one", serde_json::Value::Null, Some(0)).await.unwrap();
+            assert_eq!(backend.get("A:1").await.unwrap(), None);
+            assert!(backend.list("A:").await.unwrap().is_empty());
+        });
+    }
+}
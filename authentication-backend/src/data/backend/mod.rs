@@ -0,0 +1,26 @@
+pub mod memory;
+pub mod workers_kv;
+
+use async_trait::async_trait;
+
+pub use memory::MemoryBackend;
+pub use workers_kv::WorkersKvBackend;
+
+#[derive(Debug, Clone)]
+pub struct ListKey {
+    pub name: String,
+    // unix timestamp in seconds when the key expires
+    pub expiration: Option<u64>,
+    pub metadata: Option<serde_json::Value>,
+}
+
+// Storage used by `AuthenticationData`, modeled after Workers KV so that other stores can be
+// plugged in, e.g. to test the data layer off Cloudflare
+#[async_trait(?Send)]
+pub trait KeyValueBackend {
+    async fn get(&self, key: &str) -> worker::Result<Option<String>>;
+    async fn get_with_metadata(&self, key: &str) -> worker::Result<Option<(String, serde_json::Value)>>;
+    async fn put(&self, key: &str, value: &str, metadata: serde_json::Value, expiration_ttl: Option<u64>) -> worker::Result<()>;
+    async fn delete(&self, key: &str) -> worker::Result<()>;
+    async fn list(&self, prefix: &str) -> worker::Result<Vec<ListKey>>;
+}
@@ -0,0 +1,57 @@
+use async_trait::async_trait;
+use worker::kv::KvStore;
+
+use super::{KeyValueBackend, ListKey};
+
+pub struct WorkersKvBackend {
+    kv: KvStore,
+}
+
+impl WorkersKvBackend {
+    pub fn new(kv: KvStore) -> Self {
+        Self { kv }
+    }
+}
+
+#[async_trait(?Send)]
+impl KeyValueBackend for WorkersKvBackend {
+    async fn get(&self, key: &str) -> worker::Result<Option<String>> {
+        Ok(self.kv.get(key).await?.map(worker::kv::KvValue::as_string))
+    }
+
+    async fn get_with_metadata(&self, key: &str) -> worker::Result<Option<(String, serde_json::Value)>> {
+        let value = self.kv.get_with_metadata::<serde_json::Value>(key).await?;
+        Ok(value.map(|(value, metadata)| (value.as_string(), metadata)))
+    }
+
+    async fn put(&self, key: &str, value: &str, metadata: serde_json::Value, expiration_ttl: Option<u64>) -> worker::Result<()> {
+        let mut put = self.kv.put(key, value)?.metadata(metadata)?;
+        if let Some(expiration_ttl) = expiration_ttl {
+            put = put.expiration_ttl(expiration_ttl);
+        }
+        put.execute().await.map_err(std::convert::Into::into)
+    }
+
+    async fn delete(&self, key: &str) -> worker::Result<()> {
+        self.kv.delete(key).await.map_err(std::convert::Into::into)
+    }
+
+    async fn list(&self, prefix: &str) -> worker::Result<Vec<ListKey>> {
+        let mut keys = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut list = self.kv.list().prefix(prefix.to_string());
+            if let Some(cursor) = cursor {
+                list = list.cursor(cursor);
+            }
+            let response = list.execute().await?;
+            keys.extend(response.keys.into_iter().map(|key| ListKey { name: key.name, expiration: key.expiration, metadata: key.metadata }));
+
+            if response.list_complete || response.cursor.is_none() {
+                break;
+            }
+            cursor = response.cursor;
+        }
+        Ok(keys)
+    }
+}
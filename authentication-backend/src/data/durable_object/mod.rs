@@ -0,0 +1,325 @@
+pub mod object;
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize, Deserialize};
+use worker::{ObjectNamespace, wasm_bindgen::JsValue};
+
+use super::{AuthenticationData, StateKind, email_change::{EmailChange, EmailChangeData}, login::{LoginData, LoginSession}, password_reset::PasswordResetData, profile::{ProfileData, UserProfile, UserProfileMetadata}, register::RegistrationData};
+use crate::utils::normalize_mail;
+
+const USER_STATE_BINDING: &str = "USER_STATE";
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum UserStateCommand {
+    GetProfile,
+    SaveProfile { profile: UserProfile, metadata: UserProfileMetadata },
+    CreateProfile { profile: UserProfile, metadata: UserProfileMetadata },
+    GetPendingProfile,
+    SavePendingProfile { profile: UserProfile, metadata: UserProfileMetadata, ttl: u64 },
+    CreatePendingProfile { profile: UserProfile, metadata: UserProfileMetadata, ttl: u64 },
+    PromotePendingProfile { profile: UserProfile, metadata: UserProfileMetadata },
+    RemovePendingProfile,
+    TooManyAttempts { kind: StateKind },
+    GetState { kind: StateKind },
+    SetState { kind: StateKind, value: String },
+    StartState { kind: StateKind, value: String },
+    RemoveState { kind: StateKind },
+}
+
+// Keeps per username state in a `UserState` Durable Object, giving atomic check and set for
+// handshake states, rate limits and profile creation. Sessions are looked up by id rather than
+// username, so they stay on Workers KV.
+pub struct DurableObjectData {
+    namespace: ObjectNamespace,
+    sessions: AuthenticationData,
+}
+
+impl DurableObjectData {
+    pub fn new<D>(ctx: &worker::RouteContext<D>) -> worker::Result<Self> {
+        Ok(Self {
+            namespace: ctx.durable_object(USER_STATE_BINDING)?,
+            sessions: AuthenticationData::new(ctx)?,
+        })
+    }
+
+    async fn call<T: DeserializeOwned>(&self, username: &str, command: &UserStateCommand) -> worker::Result<T> {
+        let stub = self.namespace.id_from_name(username)?.get_stub()?;
+
+        let mut req_init = worker::RequestInit::new();
+        req_init.with_method(worker::Method::Post).with_body(Some(JsValue::from_str(&serde_json::to_string(command).map_err(|err| format!("{}",err))?)));
+        let req = worker::Request::new_with_init("https://user-state/", &req_init)?;
+
+        let mut response = stub.fetch_with_request(req).await?;
+        if response.status_code() != 200 {
+            return Err(response.text().await?.into());
+        }
+        response.json().await
+    }
+
+    async fn set_email_change_state(&self, username: &str, kind: StateKind, change: &EmailChange) -> worker::Result<()> {
+        let value = serde_json::to_vec(change).map_err(|err| format!("{}",err))?;
+        self.call::<bool>(username, &UserStateCommand::SetState { kind, value: base64::encode(value) }).await?;
+        Ok(())
+    }
+
+    async fn get_email_change_state(&self, username: &str, kind: StateKind) -> worker::Result<Option<EmailChange>> {
+        match self.get_state(username, kind).await? {
+            Some(change) => Ok(Some(serde_json::from_slice(&change).map_err(|err| format!("{}",err))?)),
+            None => Ok(None),
+        }
+    }
+
+    // Indexes the email of a saved profile unless another profile has it
+    async fn claim_mail(&self, username: &str, mail: &str) -> worker::Result<()> {
+        if self.mail_owner(mail).await?.is_none() {
+            self.sessions.index_mail(username, mail).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_state(&self, username: &str, kind: StateKind) -> worker::Result<Option<Vec<u8>>> {
+        let state = self.call::<Option<String>>(username, &UserStateCommand::GetState { kind }).await?;
+        if let Some(state) = state {
+            let state = base64::decode(state).map_err(|err| format!("{}",err))?;
+            return Ok(Some(state))
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait(?Send)]
+impl ProfileData for DurableObjectData {
+    async fn profile_already_registered(&self, username: &str) -> worker::Result<bool> {
+        Ok(self.get_profile(username).await?.is_some())
+    }
+
+    async fn profile_already_registered_waiting_mail_confirm(&self, username: &str) -> worker::Result<bool> {
+        Ok(self.get_pending_profile(username).await?.is_some())
+    }
+
+    async fn save_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::SaveProfile { profile: profile.clone(), metadata: metadata.clone() }).await?;
+        self.claim_mail(username, &profile.mail).await
+    }
+
+    // Objects are per username, so emails are indexed on Workers KV like sessions
+    async fn mail_owner(&self, mail: &str) -> worker::Result<Option<String>> {
+        if let Some(username) = self.sessions.indexed_mail_owner(mail).await? {
+            if self.get_profile(&username).await?.is_some_and(|(profile, _)| normalize_mail(&profile.mail) == normalize_mail(mail)) {
+                return Ok(Some(username));
+            }
+        }
+        Ok(None)
+    }
+
+    // Objects can't be listed, profiles saved before the index are only added when saved again
+    async fn index_profile_mails(&self) -> worker::Result<usize> {
+        Err("Durable Object profiles can't be listed to index their emails".into())
+    }
+
+    async fn get_profile(&self, username: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
+        self.call(username, &UserStateCommand::GetProfile).await
+    }
+
+    async fn create_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata) -> worker::Result<bool> {
+        let created = self.call(username, &UserStateCommand::CreateProfile { profile: profile.clone(), metadata: metadata.clone() }).await?;
+        if created {
+            self.claim_mail(username, &profile.mail).await?;
+        }
+        Ok(created)
+    }
+
+    async fn save_pending_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata, ttl: u64) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::SavePendingProfile { profile: profile.clone(), metadata: metadata.clone(), ttl }).await?;
+        Ok(())
+    }
+
+    async fn get_pending_profile(&self, username: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
+        self.call(username, &UserStateCommand::GetPendingProfile).await
+    }
+
+    async fn remove_pending_profile(&self, username: &str) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::RemovePendingProfile).await?;
+        Ok(())
+    }
+
+    async fn create_pending_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata, ttl: u64) -> worker::Result<bool> {
+        self.call(username, &UserStateCommand::CreatePendingProfile { profile: profile.clone(), metadata: metadata.clone(), ttl }).await
+    }
+
+    async fn promote_pending_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata) -> worker::Result<bool> {
+        let promoted = self.call(username, &UserStateCommand::PromotePendingProfile { profile: profile.clone(), metadata: metadata.clone() }).await?;
+        if promoted {
+            self.claim_mail(username, &profile.mail).await?;
+        }
+        Ok(promoted)
+    }
+}
+
+#[async_trait(?Send)]
+impl RegistrationData for DurableObjectData {
+    async fn too_many_registration_attempts(&self, username: &str) -> worker::Result<bool> {
+        self.call(username, &UserStateCommand::TooManyAttempts { kind: StateKind::Registration }).await
+    }
+
+    async fn set_registration_state(&self, username: &str, state: Vec<u8>) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::SetState { kind: StateKind::Registration, value: base64::encode(state) }).await?;
+        Ok(())
+    }
+
+    async fn get_registration_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::Registration).await
+    }
+
+    async fn remove_registration_state(&self, username: &str) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::RemoveState { kind: StateKind::Registration }).await?;
+        Ok(())
+    }
+
+    async fn start_registration(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        self.call(username, &UserStateCommand::StartState { kind: StateKind::Registration, value: base64::encode(state) }).await
+    }
+
+    async fn start_password_upgrade(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        self.call(username, &UserStateCommand::StartState { kind: StateKind::PasswordUpgrade, value: base64::encode(state) }).await
+    }
+
+    async fn get_password_upgrade_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::PasswordUpgrade).await
+    }
+
+    async fn remove_password_upgrade_state(&self, username: &str) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::RemoveState { kind: StateKind::PasswordUpgrade }).await?;
+        Ok(())
+    }
+
+    async fn start_password_change(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        self.call(username, &UserStateCommand::StartState { kind: StateKind::PasswordChange, value: base64::encode(state) }).await
+    }
+
+    async fn get_password_change_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::PasswordChange).await
+    }
+
+    async fn remove_password_change_state(&self, username: &str) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::RemoveState { kind: StateKind::PasswordChange }).await?;
+        Ok(())
+    }
+
+    async fn start_password_reset(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        self.call(username, &UserStateCommand::StartState { kind: StateKind::PasswordResetRegistration, value: base64::encode(state) }).await
+    }
+
+    async fn get_password_reset_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::PasswordResetRegistration).await
+    }
+
+    async fn remove_password_reset_state(&self, username: &str) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::RemoveState { kind: StateKind::PasswordResetRegistration }).await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl LoginData for DurableObjectData {
+    async fn too_many_login_attempts(&self, username: &str) -> worker::Result<bool> {
+        self.call(username, &UserStateCommand::TooManyAttempts { kind: StateKind::Login }).await
+    }
+
+    async fn set_login_state(&self, username: &str, state: Vec<u8>) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::SetState { kind: StateKind::Login, value: base64::encode(state) }).await?;
+        Ok(())
+    }
+
+    async fn get_login_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::Login).await
+    }
+
+    async fn remove_login_state(&self, username: &str) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::RemoveState { kind: StateKind::Login }).await?;
+        Ok(())
+    }
+
+    async fn start_login(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        self.call(username, &UserStateCommand::StartState { kind: StateKind::Login, value: base64::encode(state) }).await
+    }
+
+    async fn set_login_session(&self, session: &LoginSession, expiration_ttl: u64) -> worker::Result<()> {
+        self.sessions.set_login_session(session, expiration_ttl).await
+    }
+
+    async fn too_many_login_sessions_attempts(&self, username: &str) -> worker::Result<bool> {
+        self.sessions.too_many_login_sessions_attempts(username).await
+    }
+
+    async fn get_login_session(&self, session_id: &str) -> worker::Result<Option<LoginSession>> {
+        self.sessions.get_login_session(session_id).await
+    }
+
+    async fn list_login_sessions(&self, username: &str) -> worker::Result<Vec<LoginSession>> {
+        self.sessions.list_login_sessions(username).await
+    }
+
+    async fn remove_login_session(&self, session_id: &str, username: &str) -> worker::Result<()> {
+        self.sessions.remove_login_session(session_id, username).await
+    }
+
+    async fn migrate_legacy_login_sessions(&self, absolute_ttl: u64) -> worker::Result<usize> {
+        self.sessions.migrate_legacy_login_sessions(absolute_ttl).await
+    }
+}
+
+#[async_trait(?Send)]
+impl PasswordResetData for DurableObjectData {
+    async fn too_many_password_reset_requests(&self, username: &str) -> worker::Result<bool> {
+        self.call(username, &UserStateCommand::TooManyAttempts { kind: StateKind::PasswordReset }).await
+    }
+
+    async fn set_password_reset_token(&self, username: &str, token_hash: &[u8]) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::SetState { kind: StateKind::PasswordReset, value: base64::encode(token_hash) }).await?;
+        Ok(())
+    }
+
+    async fn get_password_reset_token(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::PasswordReset).await
+    }
+
+    async fn remove_password_reset_token(&self, username: &str) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::RemoveState { kind: StateKind::PasswordReset }).await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl EmailChangeData for DurableObjectData {
+    async fn too_many_email_change_requests(&self, username: &str) -> worker::Result<bool> {
+        self.call(username, &UserStateCommand::TooManyAttempts { kind: StateKind::EmailChange }).await
+    }
+
+    async fn set_email_change(&self, username: &str, change: &EmailChange) -> worker::Result<()> {
+        self.set_email_change_state(username, StateKind::EmailChange, change).await
+    }
+
+    async fn get_email_change(&self, username: &str) -> worker::Result<Option<EmailChange>> {
+        self.get_email_change_state(username, StateKind::EmailChange).await
+    }
+
+    async fn remove_email_change(&self, username: &str) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::RemoveState { kind: StateKind::EmailChange }).await?;
+        Ok(())
+    }
+
+    async fn set_email_revert(&self, username: &str, revert: &EmailChange) -> worker::Result<()> {
+        self.set_email_change_state(username, StateKind::EmailRevert, revert).await
+    }
+
+    async fn get_email_revert(&self, username: &str) -> worker::Result<Option<EmailChange>> {
+        self.get_email_change_state(username, StateKind::EmailRevert).await
+    }
+
+    async fn remove_email_revert(&self, username: &str) -> worker::Result<()> {
+        self.call::<bool>(username, &UserStateCommand::RemoveState { kind: StateKind::EmailRevert }).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use serde::{de::DeserializeOwned, Serialize, Deserialize};
+use worker::{async_trait, durable_object, js_sys, wasm_bindgen::{self, JsValue}, wasm_bindgen_futures, worker_sys, Env, Request, Response, Result, State};
+
+use super::UserStateCommand;
+use crate::{data::{StateKind, profile::{UserProfile, UserProfileMetadata}}, utils::now_millis};
+
+const PROFILE_KEY: &str = "profile";
+const PENDING_PROFILE_KEY: &str = "pending_profile";
+
+#[derive(Serialize, Deserialize)]
+struct StoredState {
+    value: String,
+    // creation time in millis
+    created: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PendingProfile {
+    profile: UserProfile,
+    metadata: UserProfileMetadata,
+    // expiration time in millis
+    expires: u64,
+}
+
+// One object per username holding its profile and in-flight states. Requests to an object are
+// processed one at a time, so every command is applied atomically.
+#[durable_object]
+pub struct UserState {
+    state: State,
+}
+
+impl UserState {
+    // `None` only for a missing key, storage and deserialization errors are passed on so a command
+    // never overwrites a record it couldn't read
+    async fn get_stored<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        // `get` reports a missing key as an error like any other, `get_multiple` leaves it out
+        let values = self.state.storage().get_multiple(vec![key]).await?;
+        let value = values.get(&JsValue::from_str(key));
+        if value.is_undefined() {
+            return Ok(None);
+        }
+        let value = js_sys::JSON::stringify(&value)?.as_string().unwrap_or_default();
+        Ok(Some(serde_json::from_str(&value).map_err(|err| format!("{}",err))?))
+    }
+
+    async fn get_state(&self, kind: StateKind) -> Result<Option<StoredState>> {
+        let stored = self.get_stored::<StoredState>(kind.key()).await?;
+        // Durable Object storage has no expiration, so states are expired on read
+        Ok(stored.filter(|stored| stored.created + kind.ttl() * 1000 > now_millis()))
+    }
+
+    async fn too_many_attempts(&self, kind: StateKind) -> Result<bool> {
+        Ok(self.get_state(kind).await?.is_some_and(|stored| stored.created + kind.throttle() * 1000 > now_millis()))
+    }
+
+    async fn set_state(&self, kind: StateKind, value: String) -> Result<()> {
+        self.state.storage().put(kind.key(), StoredState { value, created: now_millis() }).await
+    }
+
+    async fn get_profile(&self) -> Result<Option<(UserProfile, UserProfileMetadata)>> {
+        self.get_stored(PROFILE_KEY).await
+    }
+
+    async fn get_pending_profile(&self) -> Result<Option<(UserProfile, UserProfileMetadata)>> {
+        let pending = self.get_stored::<PendingProfile>(PENDING_PROFILE_KEY).await?;
+        Ok(pending.filter(|pending| pending.expires > now_millis()).map(|pending| (pending.profile, pending.metadata)))
+    }
+
+    async fn save_pending_profile(&self, profile: UserProfile, metadata: UserProfileMetadata, ttl: u64) -> Result<()> {
+        self.state.storage().put(PENDING_PROFILE_KEY, PendingProfile { profile, metadata, expires: now_millis() + ttl * 1000 }).await
+    }
+}
+
+#[durable_object]
+impl DurableObject for UserState {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&mut self, mut req: Request) -> Result<Response> {
+        let command = match req.json::<UserStateCommand>().await {
+            Ok(command) => command,
+            Err(err) => return Response::error(format!("{}", err), 400),
+        };
+
+        match command {
+            UserStateCommand::GetProfile => Response::from_json(&self.get_profile().await?),
+            UserStateCommand::SaveProfile { profile, metadata } => {
+                self.state.storage().put(PROFILE_KEY, (profile, metadata)).await?;
+                Response::from_json(&true)
+            }
+            UserStateCommand::CreateProfile { profile, metadata } => {
+                if self.get_profile().await?.is_some() {
+                    return Response::from_json(&false);
+                }
+                self.state.storage().put(PROFILE_KEY, (profile, metadata)).await?;
+                Response::from_json(&true)
+            }
+            UserStateCommand::GetPendingProfile => Response::from_json(&self.get_pending_profile().await?),
+            UserStateCommand::SavePendingProfile { profile, metadata, ttl } => {
+                self.save_pending_profile(profile, metadata, ttl).await?;
+                Response::from_json(&true)
+            }
+            UserStateCommand::CreatePendingProfile { profile, metadata, ttl } => {
+                if self.get_profile().await?.is_some() || self.get_pending_profile().await?.is_some() {
+                    return Response::from_json(&false);
+                }
+                self.save_pending_profile(profile, metadata, ttl).await?;
+                Response::from_json(&true)
+            }
+            UserStateCommand::PromotePendingProfile { profile, metadata } => {
+                if self.get_profile().await?.is_some() {
+                    return Response::from_json(&false);
+                }
+                self.state.storage().put(PROFILE_KEY, (profile, metadata)).await?;
+                self.state.storage().delete(PENDING_PROFILE_KEY).await?;
+                Response::from_json(&true)
+            }
+            UserStateCommand::RemovePendingProfile => {
+                self.state.storage().delete(PENDING_PROFILE_KEY).await?;
+                Response::from_json(&true)
+            }
+            UserStateCommand::TooManyAttempts { kind } => Response::from_json(&self.too_many_attempts(kind).await?),
+            UserStateCommand::GetState { kind } => Response::from_json(&self.get_state(kind).await?.map(|stored| stored.value)),
+            UserStateCommand::SetState { kind, value } => {
+                self.set_state(kind, value).await?;
+                Response::from_json(&true)
+            }
+            UserStateCommand::StartState { kind, value } => {
+                if self.too_many_attempts(kind).await? {
+                    return Response::from_json(&false);
+                }
+                self.set_state(kind, value).await?;
+                Response::from_json(&true)
+            }
+            UserStateCommand::RemoveState { kind } => {
+                self.state.storage().delete(kind.key()).await?;
+                Response::from_json(&true)
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use async_trait::async_trait;
+use serde::{Serialize, Deserialize};
+
+use super::{AuthenticationData, backend::KeyValueBackend};
+use crate::utils::now_millis;
+
+const EMAIL_CHANGE_PREFIX: &str = "EMAIL_CHANGE";
+const EMAIL_REVERT_PREFIX: &str = "EMAIL_REVERT";
+
+// The new address has a day to be confirmed, the old one a week to undo the change
+pub const EMAIL_CHANGE_TTL: u64 = 24 * 60 * 60;
+pub const EMAIL_REVERT_TTL: u64 = 7 * 24 * 60 * 60;
+
+// An address waiting to be confirmed, or the previous one an account can go back to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EmailChange {
+    pub mail: String,
+    // SHA-256 of the key sent by email
+    pub key_hash: Vec<u8>,
+}
+
+#[async_trait(?Send)]
+pub trait EmailChangeData {
+    async fn too_many_email_change_requests(&self, username: &str) -> worker::Result<bool>;
+    async fn set_email_change(&self, username: &str, change: &EmailChange) -> worker::Result<()>;
+    async fn get_email_change(&self, username: &str) -> worker::Result<Option<EmailChange>>;
+    async fn remove_email_change(&self, username: &str) -> worker::Result<()>;
+    async fn set_email_revert(&self, username: &str, revert: &EmailChange) -> worker::Result<()>;
+    async fn get_email_revert(&self, username: &str) -> worker::Result<Option<EmailChange>>;
+    async fn remove_email_revert(&self, username: &str) -> worker::Result<()>;
+}
+
+impl<B: KeyValueBackend> AuthenticationData<B> {
+    async fn put_email_change(&self, key: &str, change: &EmailChange, ttl: u64) -> worker::Result<()> {
+        self.put(key, &serde_json::to_string(change).map_err(|err| format!("{}",err))?, now_millis(), Some(ttl)).await
+    }
+
+    async fn get_email_change_entry(&self, key: &str, ttl: u64) -> worker::Result<Option<EmailChange>> {
+        if let Some((change, creation_time)) = self.get_with_metadata::<u64>(key).await? {
+            // KV expiration is only eventually applied, so the age is checked too
+            if creation_time + ttl * 1000 < now_millis() {
+                return Ok(None)
+            }
+            return Ok(Some(serde_json::from_str(&change).map_err(|err| format!("{}",err))?))
+        }
+        Ok(None)
+    }
+}
+
+#[async_trait(?Send)]
+impl<B: KeyValueBackend> EmailChangeData for AuthenticationData<B> {
+    async fn too_many_email_change_requests(&self, username: &str) -> worker::Result<bool> {
+        let change = self.get_with_metadata::<u64>(&format!("{}:{}", EMAIL_CHANGE_PREFIX, username)).await?;
+        if let Some((_, creation_time)) = change {
+            Ok((creation_time + 5 * 60 * 1000) > now_millis())
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn set_email_change(&self, username: &str, change: &EmailChange) -> worker::Result<()> {
+        self.put_email_change(&format!("{}:{}", EMAIL_CHANGE_PREFIX, username), change, EMAIL_CHANGE_TTL).await
+    }
+
+    async fn get_email_change(&self, username: &str) -> worker::Result<Option<EmailChange>> {
+        self.get_email_change_entry(&format!("{}:{}", EMAIL_CHANGE_PREFIX, username), EMAIL_CHANGE_TTL).await
+    }
+
+    async fn remove_email_change(&self, username: &str) -> worker::Result<()> {
+        self.kv.delete(&format!("{}:{}", EMAIL_CHANGE_PREFIX, username)).await
+    }
+
+    async fn set_email_revert(&self, username: &str, revert: &EmailChange) -> worker::Result<()> {
+        self.put_email_change(&format!("{}:{}", EMAIL_REVERT_PREFIX, username), revert, EMAIL_REVERT_TTL).await
+    }
+
+    async fn get_email_revert(&self, username: &str) -> worker::Result<Option<EmailChange>> {
+        self.get_email_change_entry(&format!("{}:{}", EMAIL_REVERT_PREFIX, username), EMAIL_REVERT_TTL).await
+    }
+
+    async fn remove_email_revert(&self, username: &str) -> worker::Result<()> {
+        self.kv.delete(&format!("{}:{}", EMAIL_REVERT_PREFIX, username)).await
+    }
+}
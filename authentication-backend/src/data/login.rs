@@ -1,11 +1,27 @@
 use async_trait::async_trait;
-use worker::Date;
+use serde::{Serialize, Deserialize};
 
-use super::AuthenticationData;
+use super::{AuthenticationData, backend::KeyValueBackend};
+use crate::utils::now_millis;
 
 const LOGIN_STATE_PREFIX: &str = "LOGIN_STATE";
 
-const LOGIN_SESSION_PREFIX: &str = "LOGIN_STATE";
+const LOGIN_SESSION_PREFIX: &str = "LOGIN_SESSION";
+const LOGIN_SESSION_INDEX_PREFIX: &str = "LOGIN_SESSION_INDEX";
+
+// Sessions used to be written under the login state prefix, without any expiration
+const LEGACY_LOGIN_SESSION_PREFIX: &str = "LOGIN_STATE";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoginSession {
+    pub id: String,
+    pub username: String,
+    // creation time in millis
+    pub created: u64,
+    // last time the session was used in millis
+    #[serde(default)]
+    pub last_used: u64,
+}
 
 #[async_trait(?Send)]
 pub trait LoginData {
@@ -13,65 +29,147 @@ pub trait LoginData {
     async fn set_login_state(&self, username: &str, state: Vec<u8>) -> worker::Result<()>;
     async fn get_login_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>>;
     async fn remove_login_state(&self, username: &str) -> worker::Result<()>;
-    async fn set_login_session(&self, username: &str, session_key: &[u8]) -> worker::Result<()>;
+    // Saves the login state unless there was a recent attempt, returns if it was saved.
+    // Backends with strong consistency override it to make the check and set atomic.
+    async fn start_login(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        if self.too_many_login_attempts(username).await? {
+            return Ok(false);
+        }
+        self.set_login_state(username, state).await?;
+        Ok(true)
+    }
+    async fn set_login_session(&self, session: &LoginSession, expiration_ttl: u64) -> worker::Result<()>;
     async fn too_many_login_sessions_attempts(&self, username: &str) -> worker::Result<bool>;
-    async fn get_login_session(&self, username: &str) -> worker::Result<Option<Vec<u8>>>;
-    async fn remove_login_session(&self, username: &str) -> worker::Result<()>;
+    async fn get_login_session(&self, session_id: &str) -> worker::Result<Option<LoginSession>>;
+    async fn list_login_sessions(&self, username: &str) -> worker::Result<Vec<LoginSession>>;
+    async fn remove_login_session(&self, session_id: &str, username: &str) -> worker::Result<()>;
+    async fn migrate_legacy_login_sessions(&self, absolute_ttl: u64) -> worker::Result<usize>;
 }
 
 #[async_trait(?Send)]
-impl LoginData for AuthenticationData {
+impl<B: KeyValueBackend> LoginData for AuthenticationData<B> {
     async fn too_many_login_attempts(&self, username: &str) -> worker::Result<bool> {
-        let state = self.kv.get_with_metadata::<u64>(&format!("{}:{}", LOGIN_STATE_PREFIX, username)).await?;
+        let state = self.get_with_metadata::<u64>(&format!("{}:{}", LOGIN_STATE_PREFIX, username)).await?;
         if let Some((_, creation_time)) = state {
-            Ok((creation_time + 5 * 1000) > Date::now().as_millis())
+            Ok((creation_time + 5 * 1000) > now_millis())
         } else {
             Ok(false)
         }
     }
 
     async fn set_login_state(&self, username: &str, state: Vec<u8>) -> worker::Result<()> {
-        self.kv.put(&format!("{}:{}", LOGIN_STATE_PREFIX, username), base64::encode(state))?.metadata( Date::now().as_millis())?.expiration_ttl(60).execute().await?;
+        self.put(&format!("{}:{}", LOGIN_STATE_PREFIX, username), &base64::encode(state), now_millis(), Some(60)).await?;
         Ok(())
     }
 
     async fn get_login_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
-        let state = self.kv.get_with_metadata::<u64>(&format!("{}:{}", LOGIN_STATE_PREFIX, username)).await?;
+        let state = self.get_with_metadata::<u64>(&format!("{}:{}", LOGIN_STATE_PREFIX, username)).await?;
         if let Some((state, _)) = state {
-            let state = base64::decode(state.as_string()).map_err(|err| format!("{}",err))?;
+            let state = base64::decode(state).map_err(|err| format!("{}",err))?;
             return Ok(Some(state))
         }
         Ok(None)
     }
 
     async fn remove_login_state(&self, username: &str) -> worker::Result<()> {
-        self.kv.delete(&format!("{}:{}", LOGIN_STATE_PREFIX, username)).await.map_err(std::convert::Into::into)
+        self.kv.delete(&format!("{}:{}", LOGIN_STATE_PREFIX, username)).await
     }
 
-    async fn set_login_session(&self, username: &str, session_key: &[u8]) -> worker::Result<()> {
-        self.kv.put(&format!("{}:{}", LOGIN_SESSION_PREFIX, username), base64::encode(session_key))?.metadata( Date::now().as_millis())?.execute().await?;
+    async fn set_login_session(&self, session: &LoginSession, expiration_ttl: u64) -> worker::Result<()> {
+        // KV doesn't accept expirations under 60 seconds
+        let expiration_ttl = expiration_ttl.max(60);
+        self.put(&format!("{}:{}", LOGIN_SESSION_PREFIX, session.id), &serde_json::to_string(session).map_err(|err| format!("{}",err))?, session.created, Some(expiration_ttl)).await?;
+        self.put(&format!("{}:{}:{}", LOGIN_SESSION_INDEX_PREFIX, session.username, session.id), "", session, Some(expiration_ttl)).await?;
         Ok(())
     }
 
     async fn too_many_login_sessions_attempts(&self, username: &str) -> worker::Result<bool> {
-        let state = self.kv.get_with_metadata::<u64>(&format!("{}:{}", LOGIN_SESSION_PREFIX, username)).await?;
-        if let Some((_, creation_time)) = state {
-            Ok((creation_time + 5 * 1000) > Date::now().as_millis())
-        } else {
-            Ok(false)
-        }
+        let now = now_millis();
+        let sessions = self.list_login_sessions(username).await?;
+        Ok(sessions.iter().any(|session| (session.created + 5 * 1000) > now))
     }
 
-    async fn get_login_session(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
-        let state = self.kv.get_with_metadata::<u64>(&format!("{}:{}", LOGIN_SESSION_PREFIX, username)).await?;
-        if let Some((state, _)) = state {
-            let state = base64::decode(state.as_string()).map_err(|err| format!("{}",err))?;
-            return Ok(Some(state))
+    async fn get_login_session(&self, session_id: &str) -> worker::Result<Option<LoginSession>> {
+        let session = self.kv.get(&format!("{}:{}", LOGIN_SESSION_PREFIX, session_id)).await?;
+        if let Some(session) = session {
+            return Ok(Some(serde_json::from_str(&session).map_err(|err| format!("{}",err))?))
         }
         Ok(None)
     }
 
-    async fn remove_login_session(&self, username: &str) -> worker::Result<()> {
-        self.kv.delete(&format!("{}:{}", LOGIN_SESSION_PREFIX, username)).await.map_err(std::convert::Into::into)
-    }    
-}
\ No newline at end of file
+    async fn list_login_sessions(&self, username: &str) -> worker::Result<Vec<LoginSession>> {
+        let keys = self.kv.list(&format!("{}:{}:", LOGIN_SESSION_INDEX_PREFIX, username)).await?;
+        Ok(keys.into_iter()
+            .filter_map(|key| key.metadata)
+            .filter_map(|metadata| serde_json::from_value(metadata).ok())
+            .collect())
+    }
+
+    async fn remove_login_session(&self, session_id: &str, username: &str) -> worker::Result<()> {
+        self.kv.delete(&format!("{}:{}", LOGIN_SESSION_PREFIX, session_id)).await?;
+        self.kv.delete(&format!("{}:{}:{}", LOGIN_SESSION_INDEX_PREFIX, username, session_id)).await
+    }
+
+    async fn migrate_legacy_login_sessions(&self, absolute_ttl: u64) -> worker::Result<usize> {
+        let now = now_millis();
+        let mut migrated = 0;
+        let keys = self.kv.list(&format!("{}:", LEGACY_LOGIN_SESSION_PREFIX)).await?;
+
+        // In-flight login states always have an expiration, sessions never had one
+        for key in keys.into_iter().filter(|key| key.expiration.is_none()) {
+            let session = self.kv.get(&key.name).await?.and_then(|value| serde_json::from_str::<LoginSession>(&value).ok());
+            if let Some(mut session) = session {
+                let expires_at = session.created + absolute_ttl * 1000;
+                if expires_at > now {
+                    session.last_used = session.last_used.max(session.created);
+                    self.set_login_session(&session, (expires_at - now) / 1000).await?;
+                    migrated += 1;
+                }
+            }
+            // Sessions saved per username slot can't be addressed by id anymore, so are dropped
+            self.kv.delete(&key.name).await?;
+        }
+        Ok(migrated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::{LoginData, LoginSession};
+    use crate::{data::{AuthenticationData, backend::MemoryBackend}, utils::now_millis};
+
+    #[test]
+    fn login_sessions_are_indexed_per_user() {
+        block_on(async {
+            let data = AuthenticationData::with_backend(MemoryBackend::new());
+            let now = now_millis();
+            for (id, username) in [("s1", "alice"), ("s2", "alice"), ("s3", "bob")] {
+                let session = LoginSession { id: id.to_string(), username: username.to_string(), created: now, last_used: now };
+                data.set_login_session(&session, 60).await.unwrap();
+            }
+
+            assert_eq!(data.list_login_sessions("alice").await.unwrap().len(), 2);
+            assert!(data.too_many_login_sessions_attempts("alice").await.unwrap());
+
+            data.remove_login_session("s1", "alice").await.unwrap();
+            assert!(data.get_login_session("s1").await.unwrap().is_none());
+            assert_eq!(data.get_login_session("s2").await.unwrap().unwrap().username, "alice");
+            assert_eq!(data.list_login_sessions("alice").await.unwrap().len(), 1);
+        });
+    }
+
+    #[test]
+    fn login_state_does_not_collide_with_sessions() {
+        block_on(async {
+            let data = AuthenticationData::with_backend(MemoryBackend::new());
+            data.set_login_state("alice", vec![1, 2, 3]).await.unwrap();
+            let now = now_millis();
+            let session = LoginSession { id: "alice".to_string(), username: "alice".to_string(), created: now, last_used: now };
+            data.set_login_session(&session, 60).await.unwrap();
+
+            assert_eq!(data.get_login_state("alice").await.unwrap(), Some(vec![1, 2, 3]));
+        });
+    }
+}
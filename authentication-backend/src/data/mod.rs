@@ -1,22 +1,115 @@
+pub mod audit;
+pub mod backend;
+pub mod durable_object;
+pub mod email_change;
+pub mod sql;
 pub mod register;
 pub mod profile;
 pub mod login;
+pub mod password_reset;
 
-use worker::{kv::KvStore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::utils::unwrap_res_abort;
+
+use self::{audit::AuditData, backend::{KeyValueBackend, WorkersKvBackend}, email_change::{EmailChangeData, EMAIL_CHANGE_TTL, EMAIL_REVERT_TTL}, login::LoginData, password_reset::PasswordResetData, profile::ProfileData, register::RegistrationData};
 
 const AUTHENTICATION_KV: &str = "AUTHENTICATION";
 
-pub struct AuthenticationData{
-    kv: KvStore,
+// Short lived per username states, for the backends that keep them in a single place
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum StateKind {
+    Registration,
+    // Re-registration of the same password, kept apart so it can't be finished as a change
+    PasswordUpgrade,
+    // New password of a logged in user, apart from registrations that anyone can start
+    PasswordChange,
+    // New password of a reset, the link token is `PasswordReset`
+    PasswordResetRegistration,
+    Login,
+    PasswordReset,
+    EmailChange,
+    EmailRevert,
+}
+
+impl StateKind {
+    pub(crate) fn key(self) -> &'static str {
+        match self {
+            StateKind::Registration => "registration_state",
+            StateKind::PasswordUpgrade => "password_upgrade_state",
+            StateKind::PasswordChange => "password_change_state",
+            StateKind::PasswordResetRegistration => "password_reset_state",
+            StateKind::Login => "login_state",
+            StateKind::PasswordReset => "password_reset",
+            StateKind::EmailChange => "email_change",
+            StateKind::EmailRevert => "email_revert",
+        }
+    }
+
+    // Time to live in seconds
+    pub(crate) fn ttl(self) -> u64 {
+        match self {
+            StateKind::Registration | StateKind::PasswordUpgrade | StateKind::PasswordChange | StateKind::PasswordResetRegistration | StateKind::Login => 60,
+            StateKind::PasswordReset => 60 * 60,
+            StateKind::EmailChange => EMAIL_CHANGE_TTL,
+            StateKind::EmailRevert => EMAIL_REVERT_TTL,
+        }
+    }
+
+    // Minimum time between attempts in seconds, same as the KV storage
+    pub(crate) fn throttle(self) -> u64 {
+        match self {
+            StateKind::Registration | StateKind::PasswordUpgrade | StateKind::PasswordChange | StateKind::PasswordResetRegistration => 15,
+            StateKind::Login => 5,
+            StateKind::PasswordReset | StateKind::EmailChange => 5 * 60,
+            StateKind::EmailRevert => 0,
+        }
+    }
 }
 
-impl<'a> AuthenticationData {
-    pub fn new<D>(ctx: &'a worker::RouteContext<D>) -> Self {
-        let kv = unwrap_res_abort(ctx.kv(AUTHENTICATION_KV));
+// Every data trait the handlers need, implemented by each storage
+pub trait AuthenticationStore: ProfileData + LoginData + RegistrationData + PasswordResetData + EmailChangeData + AuditData {}
+
+impl<T: ProfileData + LoginData + RegistrationData + PasswordResetData + EmailChangeData + AuditData> AuthenticationStore for T {}
+
+// Storage selected by the `STORAGE_BACKEND` var, Workers KV by default
+pub fn store(ctx: &worker::RouteContext<worker::Env>) -> worker::Result<Box<dyn AuthenticationStore>> {
+    let storage_backend = ctx.var("STORAGE_BACKEND").map_or_else(|_| String::new(), |backend| backend.to_string());
+    match storage_backend.as_str() {
+        "durable_object" => Ok(Box::new(durable_object::DurableObjectData::new(ctx)?)),
+        "d1" => Ok(Box::new(sql::SqlData::new(ctx.data())?)),
+        _ => Ok(Box::new(AuthenticationData::new(ctx)?)),
+    }
+}
+
+pub struct AuthenticationData<B: KeyValueBackend = WorkersKvBackend> {
+    kv: B,
+}
+
+impl<'a> AuthenticationData<WorkersKvBackend> {
+    pub fn new<D>(ctx: &'a worker::RouteContext<D>) -> worker::Result<Self> {
+        let kv = ctx.kv(AUTHENTICATION_KV)?;
+        Ok(Self::with_backend(WorkersKvBackend::new(kv)))
+    }
+}
+
+impl<B: KeyValueBackend> AuthenticationData<B> {
+    pub fn with_backend(kv: B) -> Self {
         Self {
             kv,
         }
     }
-}
\ No newline at end of file
+
+    async fn get_with_metadata<M: DeserializeOwned>(&self, key: &str) -> worker::Result<Option<(String, M)>> {
+        if let Some((value, metadata)) = self.kv.get_with_metadata(key).await? {
+            let metadata = serde_json::from_value(metadata).map_err(|err| format!("{}",err))?;
+            return Ok(Some((value, metadata)))
+        }
+        Ok(None)
+    }
+
+    async fn put<M: Serialize>(&self, key: &str, value: &str, metadata: M, expiration_ttl: Option<u64>) -> worker::Result<()> {
+        let metadata = serde_json::to_value(metadata).map_err(|err| format!("{}",err))?;
+        self.kv.put(key, value, metadata, expiration_ttl).await
+    }
+}
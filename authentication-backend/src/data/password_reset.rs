@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+
+use super::{AuthenticationData, backend::KeyValueBackend};
+use crate::utils::now_millis;
+
+const PASSWORD_RESET_PREFIX: &str = "PASSWORD_RESET";
+
+// Reset tokens are valid for an hour
+pub const PASSWORD_RESET_TTL: u64 = 60 * 60;
+
+#[async_trait(?Send)]
+pub trait PasswordResetData {
+    async fn too_many_password_reset_requests(&self, username: &str) -> worker::Result<bool>;
+    async fn set_password_reset_token(&self, username: &str, token_hash: &[u8]) -> worker::Result<()>;
+    async fn get_password_reset_token(&self, username: &str) -> worker::Result<Option<Vec<u8>>>;
+    async fn remove_password_reset_token(&self, username: &str) -> worker::Result<()>;
+}
+
+#[async_trait(?Send)]
+impl<B: KeyValueBackend> PasswordResetData for AuthenticationData<B> {
+    async fn too_many_password_reset_requests(&self, username: &str) -> worker::Result<bool> {
+        let token = self.get_with_metadata::<u64>(&format!("{}:{}", PASSWORD_RESET_PREFIX, username)).await?;
+        if let Some((_, creation_time)) = token {
+            Ok((creation_time + 5 * 60 * 1000) > now_millis())
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn set_password_reset_token(&self, username: &str, token_hash: &[u8]) -> worker::Result<()> {
+        self.put(&format!("{}:{}", PASSWORD_RESET_PREFIX, username), &base64::encode(token_hash), now_millis(), Some(PASSWORD_RESET_TTL)).await?;
+        Ok(())
+    }
+
+    async fn get_password_reset_token(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        let token = self.get_with_metadata::<u64>(&format!("{}:{}", PASSWORD_RESET_PREFIX, username)).await?;
+        if let Some((token, creation_time)) = token {
+            // KV expiration is only eventually applied, so the age is checked too
+            if creation_time + PASSWORD_RESET_TTL * 1000 < now_millis() {
+                return Ok(None)
+            }
+            let token = base64::decode(token).map_err(|err| format!("{}",err))?;
+            return Ok(Some(token))
+        }
+        Ok(None)
+    }
+
+    async fn remove_password_reset_token(&self, username: &str) -> worker::Result<()> {
+        self.kv.delete(&format!("{}:{}", PASSWORD_RESET_PREFIX, username)).await
+    }
+}
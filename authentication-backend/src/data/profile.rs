@@ -1,17 +1,43 @@
 use async_trait::async_trait;
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 
-use super::AuthenticationData;
+use super::{AuthenticationData, backend::KeyValueBackend};
+use crate::utils::normalize_mail;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UserProfile {
     pub username: String,
     pub mail: String,
     pub password_file: String,
+    // base64 SHA-256 of the email verification key that was sent, empty once used
     pub email_verification: String,
+    // millis when the verification key was sent, 0 for profiles from before keys expired
+    #[serde(default)]
+    pub email_verification_issued: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl UserProfile {
+    pub fn set_email_verification(&mut self, key: &str, now: u64) {
+        self.email_verification = base64::encode(Sha256::digest(key.as_bytes()));
+        self.email_verification_issued = now;
+    }
+
+    pub fn clear_email_verification(&mut self) {
+        self.email_verification = String::new();
+        self.email_verification_issued = 0;
+    }
+
+    // Whether `key` is the verification key that was sent less than `ttl` seconds ago
+    pub fn email_verification_matches(&self, key: &str, ttl: u64, now: u64) -> bool {
+        let key_hash = base64::encode(Sha256::digest(key.as_bytes()));
+        let matches = bool::from(self.email_verification.as_bytes().ct_eq(key_hash.as_bytes()));
+        matches && self.email_verification_issued + ttl * 1000 > now
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UserProfileMetadata {
     // version
     pub v: u8,
@@ -19,44 +45,254 @@ pub struct UserProfileMetadata {
     pub e: bool,
     // account locked
     pub l: bool,
+    // id of the server key the password file is bound to
+    #[serde(default)]
+    pub k: u8,
+    // Argon2 profile the password file was made with
+    #[serde(default)]
+    pub h: u8,
+    // OPAQUE cipher suite of the password file
+    #[serde(default)]
+    pub s: u8,
 }
 
 const PROFILE_PREFIX: &str = "PROFILE";
 const PROFILE_PENDING_PREFIX: &str = "PROFILE_PENDING";
+const PROFILE_MAIL_PREFIX: &str = "PROFILE_MAIL";
 
+// Accounts whose email is not confirmed yet are kept apart from the registered ones, under a
+// pending record that expires so an abandoned registration frees the username by itself.
 #[async_trait(?Send)]
 pub trait ProfileData {
     async fn profile_already_registered(&self, username: &str) -> worker::Result<bool>;
     async fn profile_already_registered_waiting_mail_confirm(&self, username: &str) -> worker::Result<bool>;
-    async fn save_profile(&self, username: &str, profile: &UserProfile, version: u8, locked: bool, email_verified: bool) -> worker::Result<()>;
+    async fn save_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata) -> worker::Result<()>;
     async fn get_profile(&self, username: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>>;
+    // `ttl` is in seconds, saving again restarts it
+    async fn save_pending_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata, ttl: u64) -> worker::Result<()>;
+    async fn get_pending_profile(&self, username: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>>;
+    async fn remove_pending_profile(&self, username: &str) -> worker::Result<()>;
+    // Username of the registered profile with this email, whatever its case
+    async fn mail_owner(&self, mail: &str) -> worker::Result<Option<String>>;
+    // Indexes the emails of profiles saved before `mail_owner` could find them, returns how many
+    async fn index_profile_mails(&self) -> worker::Result<usize>;
+
+    // Saves the profile only if the username is free, returns if it was saved.
+    // Backends with strong consistency override it to make the check and set atomic.
+    async fn create_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata) -> worker::Result<bool> {
+        if self.get_profile(username).await?.is_some() {
+            return Ok(false);
+        }
+        self.save_profile(username, profile, metadata).await?;
+        Ok(true)
+    }
+
+    // Saves the pending profile only if the username is neither registered nor pending, returns
+    // if it was saved. Overridden like `create_profile`.
+    async fn create_pending_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata, ttl: u64) -> worker::Result<bool> {
+        if self.get_profile(username).await?.is_some() || self.get_pending_profile(username).await?.is_some() {
+            return Ok(false);
+        }
+        self.save_pending_profile(username, profile, metadata, ttl).await?;
+        Ok(true)
+    }
+
+    // Turns the pending profile into a registered one once its email is confirmed, returns false
+    // if the username was registered in the meantime
+    async fn promote_pending_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata) -> worker::Result<bool> {
+        if !self.create_profile(username, profile, metadata).await? {
+            return Ok(false);
+        }
+        self.remove_pending_profile(username).await?;
+        Ok(true)
+    }
+
+    // The registered profile, or else the pending one, for the logins of users that didn't
+    // confirm their email yet
+    async fn get_profile_or_pending(&self, username: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
+        match self.get_profile(username).await? {
+            Some(profile) => Ok(Some(profile)),
+            None => self.get_pending_profile(username).await,
+        }
+    }
 }
 
-#[async_trait(?Send)]
-impl ProfileData for AuthenticationData {
-    async fn get_profile(&self, username: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
-        let profile = self.kv.get_with_metadata::<UserProfileMetadata>(&format!("{}:{}", PROFILE_PREFIX, username)).await?;
+impl<B: KeyValueBackend> AuthenticationData<B> {
+    // Workers KV can't look profiles up by email, so each saved profile points its email to the
+    // username. Entries aren't removed when the email changes, the profile is checked instead, and
+    // are only written while no other profile has the email, so its owner keeps it. Profiles saved
+    // before the index existed are added by `index_profile_mails`.
+    pub(super) async fn index_mail(&self, username: &str, mail: &str) -> worker::Result<()> {
+        self.kv.put(&format!("{}:{}", PROFILE_MAIL_PREFIX, normalize_mail(mail)), username, serde_json::Value::Null, None).await
+    }
+
+    pub(super) async fn indexed_mail_owner(&self, mail: &str) -> worker::Result<Option<String>> {
+        self.kv.get(&format!("{}:{}", PROFILE_MAIL_PREFIX, normalize_mail(mail))).await
+    }
+
+    async fn get_profile_entry(&self, key: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
+        let profile = self.get_with_metadata::<UserProfileMetadata>(key).await?;
         if let Some((profile, metadata)) = profile {
-            let profile = profile.as_json()?;
+            let profile = serde_json::from_str(&profile).map_err(|err| format!("{}",err))?;
             return Ok(Some((profile, metadata)))
         }
         Ok(None)
     }
+}
+
+#[async_trait(?Send)]
+impl<B: KeyValueBackend> ProfileData for AuthenticationData<B> {
+    async fn get_profile(&self, username: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
+        self.get_profile_entry(&format!("{}:{}", PROFILE_PREFIX, username)).await
+    }
 
     async fn profile_already_registered(&self, username: &str) -> worker::Result<bool> {
-        let user = self.kv.get(&format!("{}:{}", PROFILE_PENDING_PREFIX, username)).await?;
+        let user = self.kv.get(&format!("{}:{}", PROFILE_PREFIX, username)).await?;
         Ok(user.is_some())
     }
 
     async fn profile_already_registered_waiting_mail_confirm(&self, username: &str) -> worker::Result<bool> {
-        let user = self.kv.get(&format!("{}:{}", PROFILE_PREFIX, username)).await?;
+        let user = self.kv.get(&format!("{}:{}", PROFILE_PENDING_PREFIX, username)).await?;
         Ok(user.is_some())
     }
 
-    async fn save_profile(&self, username: &str, profile: &UserProfile, version: u8, locked: bool, email_verified: bool) -> worker::Result<()> {
-        self.kv.put(&format!("{}:{}", PROFILE_PREFIX, username), serde_json::to_string(profile).map_err(|err| format!("{}",err))?)?
-        .metadata( UserProfileMetadata { v: version, l: locked, e: email_verified })?
-        .execute().await?;
+    async fn save_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata) -> worker::Result<()> {
+        self.put(&format!("{}:{}", PROFILE_PREFIX, username), &serde_json::to_string(profile).map_err(|err| format!("{}",err))?, metadata, None).await?;
+        if self.mail_owner(&profile.mail).await?.is_none() {
+            self.index_mail(username, &profile.mail).await?;
+        }
+        Ok(())
+    }
+
+    async fn mail_owner(&self, mail: &str) -> worker::Result<Option<String>> {
+        if let Some(username) = self.indexed_mail_owner(mail).await? {
+            if self.get_profile(&username).await?.is_some_and(|(profile, _)| normalize_mail(&profile.mail) == normalize_mail(mail)) {
+                return Ok(Some(username));
+            }
+        }
+        Ok(None)
+    }
+
+    // Of profiles sharing an email from before the index, the first listed keeps it
+    async fn index_profile_mails(&self) -> worker::Result<usize> {
+        let mut indexed = 0;
+        for key in self.kv.list(&format!("{}:", PROFILE_PREFIX)).await? {
+            if let Some((profile, _)) = self.get_profile_entry(&key.name).await? {
+                if self.mail_owner(&profile.mail).await?.is_none() {
+                    let username = key.name[PROFILE_PREFIX.len() + 1..].to_string();
+                    self.index_mail(&username, &profile.mail).await?;
+                    indexed += 1;
+                }
+            }
+        }
+        Ok(indexed)
+    }
+
+    // KV applies the expiration itself, an expired confirmation key is also refused on its own
+    async fn save_pending_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata, ttl: u64) -> worker::Result<()> {
+        self.put(&format!("{}:{}", PROFILE_PENDING_PREFIX, username), &serde_json::to_string(profile).map_err(|err| format!("{}",err))?, metadata, Some(ttl)).await?;
         Ok(())
-    }    
-}
\ No newline at end of file
+    }
+
+    async fn get_pending_profile(&self, username: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
+        self.get_profile_entry(&format!("{}:{}", PROFILE_PENDING_PREFIX, username)).await
+    }
+
+    async fn remove_pending_profile(&self, username: &str) -> worker::Result<()> {
+        self.kv.delete(&format!("{}:{}", PROFILE_PENDING_PREFIX, username)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::{ProfileData, UserProfile, UserProfileMetadata};
+    use crate::data::{AuthenticationData, backend::MemoryBackend};
+
+    fn profile(username: &str) -> UserProfile {
+        UserProfile { username: username.to_string(), mail: format!("{}@example.com", username), password_file: String::new(), email_verification: String::new(), email_verification_issued: 0 }
+    }
+
+    fn metadata(email_verified: bool) -> UserProfileMetadata {
+        UserProfileMetadata { v: 0, e: email_verified, l: false, k: 0, h: 0, s: 0 }
+    }
+
+    #[test]
+    fn pending_profiles_are_promoted_on_confirmation() {
+        block_on(async {
+            let data = AuthenticationData::with_backend(MemoryBackend::new());
+            assert!(data.create_pending_profile("alice", &profile("alice"), &metadata(false), 60).await.unwrap());
+            assert!(!data.create_pending_profile("alice", &profile("alice"), &metadata(false), 60).await.unwrap());
+            assert!(data.profile_already_registered_waiting_mail_confirm("alice").await.unwrap());
+            assert!(!data.profile_already_registered("alice").await.unwrap());
+            assert!(data.get_profile("alice").await.unwrap().is_none());
+            assert!(data.get_profile_or_pending("alice").await.unwrap().is_some());
+
+            assert!(data.promote_pending_profile("alice", &profile("alice"), &metadata(true)).await.unwrap());
+            assert!(data.profile_already_registered("alice").await.unwrap());
+            assert!(!data.profile_already_registered_waiting_mail_confirm("alice").await.unwrap());
+            assert!(!data.create_pending_profile("alice", &profile("alice"), &metadata(false), 60).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn mail_owner_follows_email_changes() {
+        block_on(async {
+            let data = AuthenticationData::with_backend(MemoryBackend::new());
+            let mut alice = profile("alice");
+            data.save_profile("alice", &alice, &metadata(true)).await.unwrap();
+            assert_eq!(data.mail_owner("alice@example.com").await.unwrap(), Some("alice".to_string()));
+
+            alice.mail = "new@example.com".to_string();
+            data.save_profile("alice", &alice, &metadata(true)).await.unwrap();
+            assert_eq!(data.mail_owner("alice@example.com").await.unwrap(), None);
+            assert_eq!(data.mail_owner("New@Example.com").await.unwrap(), Some("alice".to_string()));
+
+            // another profile saved with the address doesn't take it from its owner
+            let mut bob = profile("bob");
+            bob.mail = "new@example.com".to_string();
+            data.save_profile("bob", &bob, &metadata(true)).await.unwrap();
+            assert_eq!(data.mail_owner("new@example.com").await.unwrap(), Some("alice".to_string()));
+        });
+    }
+
+    #[test]
+    fn profiles_saved_before_the_mail_index_are_indexed() {
+        block_on(async {
+            let data = AuthenticationData::with_backend(MemoryBackend::new());
+            for username in ["alice", "bob"] {
+                data.put(&format!("PROFILE:{}", username), &serde_json::to_string(&profile(username)).unwrap(), &metadata(true), None).await.unwrap();
+            }
+            data.save_profile("carol", &profile("carol"), &metadata(true)).await.unwrap();
+            assert_eq!(data.mail_owner("alice@example.com").await.unwrap(), None);
+
+            assert_eq!(data.index_profile_mails().await.unwrap(), 2);
+            assert_eq!(data.mail_owner("alice@example.com").await.unwrap(), Some("alice".to_string()));
+            assert_eq!(data.mail_owner("bob@example.com").await.unwrap(), Some("bob".to_string()));
+            assert_eq!(data.index_profile_mails().await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn expired_pending_profiles_free_the_username() {
+        block_on(async {
+            let data = AuthenticationData::with_backend(MemoryBackend::new());
+            data.save_pending_profile("alice", &profile("alice"), &metadata(false), 0).await.unwrap();
+            assert!(!data.profile_already_registered_waiting_mail_confirm("alice").await.unwrap());
+            assert!(data.create_pending_profile("alice", &profile("alice"), &metadata(false), 60).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn email_verification_expires_and_is_single_use() {
+        let mut profile = profile("alice");
+        profile.set_email_verification("key", 1_000);
+        assert_ne!(profile.email_verification, "key");
+        assert!(profile.email_verification_matches("key", 60, 60_999));
+        assert!(!profile.email_verification_matches("other", 60, 2_000));
+        assert!(!profile.email_verification_matches("key", 60, 61_000));
+        profile.clear_email_verification();
+        assert!(!profile.email_verification_matches("key", 60, 2_000));
+    }
+}
@@ -1,9 +1,12 @@
 use async_trait::async_trait;
-use worker::Date;
 
-use super::AuthenticationData;
+use super::{AuthenticationData, backend::KeyValueBackend};
+use crate::utils::now_millis;
 
 const REGISTRATION_STATE_PREFIX: &str = "REGISTRATION_STATE";
+const PASSWORD_UPGRADE_STATE_PREFIX: &str = "PASSWORD_UPGRADE_STATE";
+const PASSWORD_CHANGE_STATE_PREFIX: &str = "PASSWORD_CHANGE_STATE";
+const PASSWORD_RESET_STATE_PREFIX: &str = "PASSWORD_RESET_STATE";
 
 
 #[async_trait(?Send)]
@@ -12,35 +15,124 @@ pub trait RegistrationData {
     async fn set_registration_state(&self, username: &str, state: Vec<u8>) -> worker::Result<()>;
     async fn get_registration_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>>;
     async fn remove_registration_state(&self, username: &str) -> worker::Result<()>;
+
+    // Saves the registration state unless there was a recent attempt, returns if it was saved.
+    // Backends with strong consistency override it to make the check and set atomic.
+    async fn start_registration(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        if self.too_many_registration_attempts(username).await? {
+            return Ok(false);
+        }
+        self.set_registration_state(username, state).await?;
+        Ok(true)
+    }
+
+    // The state of a password upgrade, same as a registration but of its own so a change can't be
+    // finished as an upgrade or the other way around
+    async fn start_password_upgrade(&self, username: &str, state: Vec<u8>) -> worker::Result<bool>;
+    async fn get_password_upgrade_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>>;
+    async fn remove_password_upgrade_state(&self, username: &str) -> worker::Result<()>;
+
+    // The state of a logged in user's password change, apart from anonymous registrations so a
+    // probe for a taken username can't hold it up
+    async fn start_password_change(&self, username: &str, state: Vec<u8>) -> worker::Result<bool>;
+    async fn get_password_change_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>>;
+    async fn remove_password_change_state(&self, username: &str) -> worker::Result<()>;
+
+    // The state of the new password of a reset, apart from registrations and changes so they
+    // can't hold each other up
+    async fn start_password_reset(&self, username: &str, state: Vec<u8>) -> worker::Result<bool>;
+    async fn get_password_reset_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>>;
+    async fn remove_password_reset_state(&self, username: &str) -> worker::Result<()>;
 }
 
-#[async_trait(?Send)]
-impl  RegistrationData for AuthenticationData {
-    async fn too_many_registration_attempts(&self, username: &str) -> worker::Result<bool> {
-        let state = self.kv.get_with_metadata::<u64>(&format!("{}:{}", REGISTRATION_STATE_PREFIX, username)).await?;
+impl<B: KeyValueBackend> AuthenticationData<B> {
+    async fn too_many_handshake_attempts(&self, prefix: &str, username: &str) -> worker::Result<bool> {
+        let state = self.get_with_metadata::<u64>(&format!("{}:{}", prefix, username)).await?;
         if let Some((_, creation_time)) = state {
-            Ok((creation_time + 15 * 1000) > Date::now().as_millis())
+            Ok((creation_time + 15 * 1000) > now_millis())
         } else {
             Ok(false)
         }
     }
 
-    async fn set_registration_state(&self, username: &str, state: Vec<u8>) -> worker::Result<()> {
-        self.kv.put(&format!("{}:{}", REGISTRATION_STATE_PREFIX, username), base64::encode(state))?.metadata( Date::now().as_millis())?.expiration_ttl(60).execute().await?;
+    async fn set_handshake_state(&self, prefix: &str, username: &str, state: Vec<u8>) -> worker::Result<()> {
+        self.put(&format!("{}:{}", prefix, username), &base64::encode(state), now_millis(), Some(60)).await?;
         Ok(())
     }
 
-    async fn get_registration_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
-        let state = self.kv.get_with_metadata::<u64>(&format!("{}:{}", REGISTRATION_STATE_PREFIX, username)).await?;
+    async fn get_handshake_state(&self, prefix: &str, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        let state = self.get_with_metadata::<u64>(&format!("{}:{}", prefix, username)).await?;
         if let Some((state, _)) = state {
-            let state = base64::decode(state.as_string()).map_err(|err| format!("{}",err))?;
+            let state = base64::decode(state).map_err(|err| format!("{}",err))?;
             return Ok(Some(state))
         }
         Ok(None)
     }
+}
+
+#[async_trait(?Send)]
+impl<B: KeyValueBackend> RegistrationData for AuthenticationData<B> {
+    async fn too_many_registration_attempts(&self, username: &str) -> worker::Result<bool> {
+        self.too_many_handshake_attempts(REGISTRATION_STATE_PREFIX, username).await
+    }
+
+    async fn set_registration_state(&self, username: &str, state: Vec<u8>) -> worker::Result<()> {
+        self.set_handshake_state(REGISTRATION_STATE_PREFIX, username, state).await
+    }
+
+    async fn get_registration_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_handshake_state(REGISTRATION_STATE_PREFIX, username).await
+    }
 
     async fn remove_registration_state(&self, username: &str) -> worker::Result<()> {
-        self.kv.delete(&format!("{}:{}", REGISTRATION_STATE_PREFIX, username)).await.map_err(std::convert::Into::into)
+        self.kv.delete(&format!("{}:{}", REGISTRATION_STATE_PREFIX, username)).await
     }
 
-}
\ No newline at end of file
+    async fn start_password_upgrade(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        if self.too_many_handshake_attempts(PASSWORD_UPGRADE_STATE_PREFIX, username).await? {
+            return Ok(false);
+        }
+        self.set_handshake_state(PASSWORD_UPGRADE_STATE_PREFIX, username, state).await?;
+        Ok(true)
+    }
+
+    async fn get_password_upgrade_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_handshake_state(PASSWORD_UPGRADE_STATE_PREFIX, username).await
+    }
+
+    async fn remove_password_upgrade_state(&self, username: &str) -> worker::Result<()> {
+        self.kv.delete(&format!("{}:{}", PASSWORD_UPGRADE_STATE_PREFIX, username)).await
+    }
+
+    async fn start_password_change(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        if self.too_many_handshake_attempts(PASSWORD_CHANGE_STATE_PREFIX, username).await? {
+            return Ok(false);
+        }
+        self.set_handshake_state(PASSWORD_CHANGE_STATE_PREFIX, username, state).await?;
+        Ok(true)
+    }
+
+    async fn get_password_change_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_handshake_state(PASSWORD_CHANGE_STATE_PREFIX, username).await
+    }
+
+    async fn remove_password_change_state(&self, username: &str) -> worker::Result<()> {
+        self.kv.delete(&format!("{}:{}", PASSWORD_CHANGE_STATE_PREFIX, username)).await
+    }
+
+    async fn start_password_reset(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        if self.too_many_handshake_attempts(PASSWORD_RESET_STATE_PREFIX, username).await? {
+            return Ok(false);
+        }
+        self.set_handshake_state(PASSWORD_RESET_STATE_PREFIX, username, state).await?;
+        Ok(true)
+    }
+
+    async fn get_password_reset_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_handshake_state(PASSWORD_RESET_STATE_PREFIX, username).await
+    }
+
+    async fn remove_password_reset_state(&self, username: &str) -> worker::Result<()> {
+        self.kv.delete(&format!("{}:{}", PASSWORD_RESET_STATE_PREFIX, username)).await
+    }
+}
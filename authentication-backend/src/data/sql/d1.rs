@@ -0,0 +1,81 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use worker::{js_sys::{self, Promise, Reflect}, wasm_bindgen::{self, prelude::*, JsCast}, wasm_bindgen_futures::JsFuture, Env};
+
+use super::SqlDatabase;
+
+// The workers crate has no D1 bindings yet, so the few calls used are declared here
+#[wasm_bindgen]
+extern "C" {
+    pub type D1Database;
+
+    #[wasm_bindgen(method, catch)]
+    fn prepare(this: &D1Database, query: &str) -> Result<D1PreparedStatement, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn exec(this: &D1Database, query: &str) -> Result<Promise, JsValue>;
+
+    type D1PreparedStatement;
+
+    #[wasm_bindgen(method, catch, variadic)]
+    fn bind(this: &D1PreparedStatement, values: Box<[JsValue]>) -> Result<D1PreparedStatement, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn all(this: &D1PreparedStatement) -> Result<Promise, JsValue>;
+
+    #[wasm_bindgen(method, catch)]
+    fn run(this: &D1PreparedStatement) -> Result<Promise, JsValue>;
+}
+
+impl D1Database {
+    pub fn from_env(env: &Env, binding: &str) -> worker::Result<Self> {
+        let database = Reflect::get(env, &JsValue::from_str(binding))?;
+        if database.is_undefined() {
+            return Err(format!("Binding `{}` is undefined.", binding).into());
+        }
+        Ok(database.unchecked_into())
+    }
+
+    fn statement(&self, sql: &str, params: &[Value]) -> worker::Result<D1PreparedStatement> {
+        let params = params.iter().map(to_js).collect::<worker::Result<Vec<_>>>()?;
+        Ok(self.prepare(sql)?.bind(params.into_boxed_slice())?)
+    }
+}
+
+fn to_js(value: &Value) -> worker::Result<JsValue> {
+    Ok(js_sys::JSON::parse(&value.to_string())?)
+}
+
+async fn to_json(promise: Promise) -> worker::Result<Value> {
+    let result = JsFuture::from(promise).await?;
+    if result.is_undefined() {
+        return Ok(Value::Null);
+    }
+    let result = String::from(js_sys::JSON::stringify(&result)?);
+    Ok(serde_json::from_str(&result)?)
+}
+
+#[async_trait(?Send)]
+impl SqlDatabase for D1Database {
+    async fn execute(&self, sql: &str, params: &[Value]) -> worker::Result<u64> {
+        let result = to_json(self.statement(sql, params)?.run()?).await?;
+        // Older runtimes return the changes at the top level instead of in `meta`
+        let changes = result.pointer("/meta/changes").or_else(|| result.get("changes"));
+        Ok(changes.and_then(Value::as_u64).unwrap_or_default())
+    }
+
+    async fn query(&self, sql: &str, params: &[Value]) -> worker::Result<Vec<Value>> {
+        let result = to_json(self.statement(sql, params)?.all()?).await?;
+        match result.get("results") {
+            Some(Value::Array(rows)) => Ok(rows.clone()),
+            _ => Ok(vec![]),
+        }
+    }
+
+    async fn execute_batch(&self, sql: &str) -> worker::Result<()> {
+        // `exec` splits statements by line, so each one has to be on a single line
+        let sql = sql.split(';').map(|statement| statement.split_whitespace().collect::<Vec<_>>().join(" ")).filter(|statement| !statement.is_empty()).collect::<Vec<_>>().join(";\n");
+        to_json(self.exec(&sql)?).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use serde_json::json;
+
+use super::SqlDatabase;
+use crate::utils::now_millis;
+
+// Schema versions in order, a migration is never edited once released, changes go in a new one
+const MIGRATIONS: &[(u32, &str)] = &[
+    (1, "
+        CREATE TABLE profiles (
+            username TEXT NOT NULL PRIMARY KEY,
+            mail TEXT NOT NULL UNIQUE,
+            password_file TEXT NOT NULL,
+            email_verification TEXT NOT NULL,
+            version INTEGER NOT NULL DEFAULT 0,
+            locked INTEGER NOT NULL DEFAULT 0,
+            email_verified INTEGER NOT NULL DEFAULT 0,
+            created INTEGER NOT NULL
+        );
+        CREATE TABLE sessions (
+            id TEXT NOT NULL PRIMARY KEY,
+            username TEXT NOT NULL,
+            created INTEGER NOT NULL,
+            last_used INTEGER NOT NULL,
+            expires INTEGER NOT NULL
+        );
+        CREATE INDEX sessions_username ON sessions(username);
+        CREATE TABLE handshake_states (
+            username TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            value TEXT NOT NULL,
+            created INTEGER NOT NULL,
+            expires INTEGER NOT NULL,
+            PRIMARY KEY (username, kind)
+        );
+        CREATE TABLE audit_events (
+            id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            event TEXT NOT NULL,
+            created INTEGER NOT NULL
+        );
+        CREATE INDEX audit_events_username ON audit_events(username, created);
+    "),
+    (2, "
+        ALTER TABLE profiles ADD COLUMN key_id INTEGER NOT NULL DEFAULT 0;
+    "),
+    (3, "
+        ALTER TABLE profiles ADD COLUMN hash_profile INTEGER NOT NULL DEFAULT 0;
+    "),
+    (4, "
+        ALTER TABLE profiles ADD COLUMN cipher_suite INTEGER NOT NULL DEFAULT 0;
+    "),
+    (5, "
+        ALTER TABLE profiles ADD COLUMN email_verification_issued INTEGER NOT NULL DEFAULT 0;
+    "),
+    (6, "
+        CREATE TABLE pending_profiles (
+            username TEXT NOT NULL PRIMARY KEY,
+            mail TEXT NOT NULL,
+            password_file TEXT NOT NULL,
+            email_verification TEXT NOT NULL,
+            email_verification_issued INTEGER NOT NULL,
+            version INTEGER NOT NULL,
+            locked INTEGER NOT NULL,
+            email_verified INTEGER NOT NULL,
+            key_id INTEGER NOT NULL,
+            hash_profile INTEGER NOT NULL,
+            cipher_suite INTEGER NOT NULL,
+            created INTEGER NOT NULL,
+            expires INTEGER NOT NULL
+        );
+    "),
+];
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |(version, _)| *version)
+}
+
+pub async fn current_version(db: &dyn SqlDatabase) -> worker::Result<u32> {
+    db.execute_batch("CREATE TABLE IF NOT EXISTS schema_migrations (version INTEGER NOT NULL PRIMARY KEY, applied INTEGER NOT NULL);").await?;
+    let rows = db.query("SELECT MAX(version) AS version FROM schema_migrations", &[]).await?;
+    Ok(rows.first().and_then(|row| row["version"].as_u64()).unwrap_or_default() as u32)
+}
+
+// Applies the pending migrations, returns the schema version the database ends up in
+pub async fn migrate(db: &dyn SqlDatabase) -> worker::Result<u32> {
+    let mut version = current_version(db).await?;
+    let applied = version;
+    for (migration_version, sql) in MIGRATIONS.iter().filter(|(migration_version, _)| *migration_version > applied) {
+        db.execute_batch(sql).await?;
+        db.execute("INSERT INTO schema_migrations (version, applied) VALUES (?1, ?2)", &[json!(migration_version), json!(now_millis())]).await?;
+        version = *migration_version;
+    }
+    Ok(version)
+}
@@ -0,0 +1,527 @@
+mod d1;
+pub mod migrations;
+#[cfg(not(target_arch = "wasm32"))]
+mod sqlite;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+pub use d1::D1Database;
+#[cfg(not(target_arch = "wasm32"))]
+pub use sqlite::SqliteDatabase;
+
+use super::{StateKind, audit::AuditData, email_change::{EmailChange, EmailChangeData}, login::{LoginData, LoginSession}, password_reset::PasswordResetData, profile::{ProfileData, UserProfile, UserProfileMetadata}, register::RegistrationData};
+use crate::utils::{normalize_mail, now_millis};
+
+const AUTHENTICATION_DB: &str = "AUTHENTICATION_DB";
+
+// Relational database used by `SqlData`, rows are returned as JSON objects keyed by column name
+#[async_trait(?Send)]
+pub trait SqlDatabase {
+    // Runs a single statement, returns the number of changed rows
+    async fn execute(&self, sql: &str, params: &[Value]) -> worker::Result<u64>;
+    async fn query(&self, sql: &str, params: &[Value]) -> worker::Result<Vec<Value>>;
+    // Runs several statements without parameters, used by the migrations
+    async fn execute_batch(&self, sql: &str) -> worker::Result<()>;
+}
+
+#[derive(Deserialize)]
+struct ProfileRow {
+    username: String,
+    mail: String,
+    password_file: String,
+    email_verification: String,
+    email_verification_issued: u64,
+    version: u8,
+    locked: i64,
+    email_verified: i64,
+    key_id: u8,
+    hash_profile: u8,
+    cipher_suite: u8,
+}
+
+impl ProfileRow {
+    fn into_profile(self) -> (UserProfile, UserProfileMetadata) {
+        let profile = UserProfile { username: self.username, mail: self.mail, password_file: self.password_file, email_verification: self.email_verification, email_verification_issued: self.email_verification_issued };
+        (profile, UserProfileMetadata { v: self.version, e: self.email_verified != 0, l: self.locked != 0, k: self.key_id, h: self.hash_profile, s: self.cipher_suite })
+    }
+}
+
+const PROFILE_COLUMNS: &str = "username, mail, password_file, email_verification, email_verification_issued, version, locked, email_verified, key_id, hash_profile, cipher_suite";
+
+const PENDING_PROFILE_COLUMNS_INSERT: &str = "INSERT INTO pending_profiles (username, mail, password_file, email_verification, email_verification_issued, version, locked, email_verified, key_id, hash_profile, cipher_suite, created, expires)";
+
+fn profile_params(username: &str, profile: &UserProfile, metadata: &UserProfileMetadata) -> [Value; 12] {
+    [json!(username), json!(profile.mail), json!(profile.password_file), json!(profile.email_verification), json!(profile.email_verification_issued), json!(metadata.v), json!(metadata.l as u8), json!(metadata.e as u8), json!(metadata.k), json!(metadata.h), json!(metadata.s), json!(now_millis())]
+}
+
+// Keeps profiles, pending profiles, sessions, handshake states and audit events in a SQL database,
+// Cloudflare D1 when deployed. Unlike the KV layout it can list users and look them up by email,
+// and usernames and emails are unique.
+pub struct SqlData<S: SqlDatabase = D1Database> {
+    db: S,
+}
+
+impl SqlData<D1Database> {
+    pub fn new(env: &worker::Env) -> worker::Result<Self> {
+        Ok(Self::with_database(D1Database::from_env(env, AUTHENTICATION_DB)?))
+    }
+}
+
+impl<S: SqlDatabase> SqlData<S> {
+    pub fn with_database(db: S) -> Self {
+        Self {
+            db,
+        }
+    }
+
+    pub async fn migrate(&self) -> worker::Result<u32> {
+        migrations::migrate(&self.db).await
+    }
+
+    pub async fn list_profiles(&self, limit: u32, offset: u32) -> worker::Result<Vec<(UserProfile, UserProfileMetadata)>> {
+        let rows = self.db.query(&format!("SELECT {} FROM profiles ORDER BY username LIMIT ?1 OFFSET ?2", PROFILE_COLUMNS), &[json!(limit), json!(offset)]).await?;
+        rows.into_iter().map(|row| Ok(serde_json::from_value::<ProfileRow>(row)?.into_profile())).collect()
+    }
+
+    pub async fn get_profile_by_mail(&self, mail: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
+        let rows = self.db.query(&format!("SELECT {} FROM profiles WHERE lower(mail) = ?1", PROFILE_COLUMNS), &[json!(normalize_mail(mail))]).await?;
+        self.first_profile(rows)
+    }
+
+    fn first_profile(&self, rows: Vec<Value>) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
+        match rows.into_iter().next() {
+            Some(row) => Ok(Some(serde_json::from_value::<ProfileRow>(row)?.into_profile())),
+            None => Ok(None),
+        }
+    }
+
+    // Creation time of the state in millis, if it hasn't expired
+    async fn state_created(&self, username: &str, kind: StateKind) -> worker::Result<Option<u64>> {
+        let rows = self.db.query("SELECT created FROM handshake_states WHERE username = ?1 AND kind = ?2 AND expires > ?3", &[json!(username), json!(kind.key()), json!(now_millis())]).await?;
+        Ok(rows.first().and_then(|row| row["created"].as_u64()))
+    }
+
+    async fn too_many_attempts(&self, username: &str, kind: StateKind) -> worker::Result<bool> {
+        let created = self.state_created(username, kind).await?;
+        Ok(created.is_some_and(|created| created + kind.throttle() * 1000 > now_millis()))
+    }
+
+    async fn get_state(&self, username: &str, kind: StateKind) -> worker::Result<Option<Vec<u8>>> {
+        let rows = self.db.query("SELECT value FROM handshake_states WHERE username = ?1 AND kind = ?2 AND expires > ?3", &[json!(username), json!(kind.key()), json!(now_millis())]).await?;
+        if let Some(state) = rows.first().and_then(|row| row["value"].as_str()) {
+            let state = base64::decode(state).map_err(|err| format!("{}",err))?;
+            return Ok(Some(state))
+        }
+        Ok(None)
+    }
+
+    async fn set_state(&self, username: &str, kind: StateKind, value: &[u8]) -> worker::Result<()> {
+        let now = now_millis();
+        self.db.execute(
+            "INSERT INTO handshake_states (username, kind, value, created, expires) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (username, kind) DO UPDATE SET value = excluded.value, created = excluded.created, expires = excluded.expires",
+            &[json!(username), json!(kind.key()), json!(base64::encode(value)), json!(now), json!(now + kind.ttl() * 1000)],
+        ).await?;
+        Ok(())
+    }
+
+    // Only replaces a state that expired or is older than the throttle, in a single statement so
+    // concurrent attempts can't both succeed
+    async fn start_state(&self, username: &str, kind: StateKind, value: &[u8]) -> worker::Result<bool> {
+        let now = now_millis();
+        let changes = self.db.execute(
+            "INSERT INTO handshake_states (username, kind, value, created, expires) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (username, kind) DO UPDATE SET value = excluded.value, created = excluded.created, expires = excluded.expires
+             WHERE handshake_states.expires <= ?4 OR handshake_states.created + ?6 <= ?4",
+            &[json!(username), json!(kind.key()), json!(base64::encode(value)), json!(now), json!(now + kind.ttl() * 1000), json!(kind.throttle() * 1000)],
+        ).await?;
+        Ok(changes > 0)
+    }
+
+    async fn get_email_change_state(&self, username: &str, kind: StateKind) -> worker::Result<Option<EmailChange>> {
+        match self.get_state(username, kind).await? {
+            Some(change) => Ok(Some(serde_json::from_slice(&change)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn remove_state(&self, username: &str, kind: StateKind) -> worker::Result<()> {
+        self.db.execute("DELETE FROM handshake_states WHERE username = ?1 AND kind = ?2", &[json!(username), json!(kind.key())]).await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl<S: SqlDatabase> ProfileData for SqlData<S> {
+    async fn profile_already_registered(&self, username: &str) -> worker::Result<bool> {
+        Ok(self.get_profile(username).await?.is_some())
+    }
+
+    async fn profile_already_registered_waiting_mail_confirm(&self, username: &str) -> worker::Result<bool> {
+        Ok(self.get_pending_profile(username).await?.is_some())
+    }
+
+    async fn save_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata) -> worker::Result<()> {
+        self.db.execute(
+            "INSERT INTO profiles (username, mail, password_file, email_verification, email_verification_issued, version, locked, email_verified, key_id, hash_profile, cipher_suite, created) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+             ON CONFLICT (username) DO UPDATE SET mail = excluded.mail, password_file = excluded.password_file, email_verification = excluded.email_verification, email_verification_issued = excluded.email_verification_issued,
+             version = excluded.version, locked = excluded.locked, email_verified = excluded.email_verified, key_id = excluded.key_id, hash_profile = excluded.hash_profile, cipher_suite = excluded.cipher_suite",
+            &profile_params(username, profile, metadata),
+        ).await?;
+        Ok(())
+    }
+
+    async fn get_profile(&self, username: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
+        let rows = self.db.query(&format!("SELECT {} FROM profiles WHERE username = ?1", PROFILE_COLUMNS), &[json!(username)]).await?;
+        self.first_profile(rows)
+    }
+
+    // `false` for a taken username, an email another profile has is an error of the `mail` UNIQUE
+    // constraint, for the caller to tell apart
+    async fn create_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata) -> worker::Result<bool> {
+        let changes = self.db.execute(
+            "INSERT INTO profiles (username, mail, password_file, email_verification, email_verification_issued, version, locked, email_verified, key_id, hash_profile, cipher_suite, created) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+             ON CONFLICT (username) DO NOTHING",
+            &profile_params(username, profile, metadata),
+        ).await?;
+        Ok(changes > 0)
+    }
+
+    async fn mail_owner(&self, mail: &str) -> worker::Result<Option<String>> {
+        Ok(self.get_profile_by_mail(mail).await?.map(|(profile, _)| profile.username))
+    }
+
+    // Emails are looked up in `profiles` itself, there is nothing to index
+    async fn index_profile_mails(&self) -> worker::Result<usize> {
+        Ok(0)
+    }
+
+    async fn save_pending_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata, ttl: u64) -> worker::Result<()> {
+        let mut params = profile_params(username, profile, metadata).to_vec();
+        params.push(json!(now_millis() + ttl * 1000));
+        self.db.execute(
+            &format!("{} VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+             ON CONFLICT (username) DO UPDATE SET mail = excluded.mail, password_file = excluded.password_file, email_verification = excluded.email_verification, email_verification_issued = excluded.email_verification_issued,
+             version = excluded.version, locked = excluded.locked, email_verified = excluded.email_verified, key_id = excluded.key_id, hash_profile = excluded.hash_profile, cipher_suite = excluded.cipher_suite, expires = excluded.expires", PENDING_PROFILE_COLUMNS_INSERT),
+            &params,
+        ).await?;
+        Ok(())
+    }
+
+    async fn get_pending_profile(&self, username: &str) -> worker::Result<Option<(UserProfile, UserProfileMetadata)>> {
+        let rows = self.db.query(&format!("SELECT {} FROM pending_profiles WHERE username = ?1 AND expires > ?2", PROFILE_COLUMNS), &[json!(username), json!(now_millis())]).await?;
+        self.first_profile(rows)
+    }
+
+    async fn remove_pending_profile(&self, username: &str) -> worker::Result<()> {
+        self.db.execute("DELETE FROM pending_profiles WHERE username = ?1", &[json!(username)]).await?;
+        Ok(())
+    }
+
+    // Only takes the place of an expired pending profile, and not of a registered username
+    async fn create_pending_profile(&self, username: &str, profile: &UserProfile, metadata: &UserProfileMetadata, ttl: u64) -> worker::Result<bool> {
+        let now = now_millis();
+        // Rows don't expire by themselves, so abandoned registrations are cleaned up here
+        self.db.execute("DELETE FROM pending_profiles WHERE expires <= ?1 AND username != ?2", &[json!(now), json!(username)]).await?;
+        let mut params = profile_params(username, profile, metadata).to_vec();
+        params.push(json!(now + ttl * 1000));
+        let changes = self.db.execute(
+            &format!("{} SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13 WHERE NOT EXISTS (SELECT 1 FROM profiles WHERE username = ?1)
+             ON CONFLICT (username) DO UPDATE SET mail = excluded.mail, password_file = excluded.password_file, email_verification = excluded.email_verification, email_verification_issued = excluded.email_verification_issued,
+             version = excluded.version, locked = excluded.locked, email_verified = excluded.email_verified, key_id = excluded.key_id, hash_profile = excluded.hash_profile, cipher_suite = excluded.cipher_suite, created = excluded.created, expires = excluded.expires
+             WHERE pending_profiles.expires <= excluded.created", PENDING_PROFILE_COLUMNS_INSERT),
+            &params,
+        ).await?;
+        Ok(changes > 0)
+    }
+}
+
+#[async_trait(?Send)]
+impl<S: SqlDatabase> RegistrationData for SqlData<S> {
+    async fn too_many_registration_attempts(&self, username: &str) -> worker::Result<bool> {
+        self.too_many_attempts(username, StateKind::Registration).await
+    }
+
+    async fn set_registration_state(&self, username: &str, state: Vec<u8>) -> worker::Result<()> {
+        self.set_state(username, StateKind::Registration, &state).await
+    }
+
+    async fn get_registration_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::Registration).await
+    }
+
+    async fn remove_registration_state(&self, username: &str) -> worker::Result<()> {
+        self.remove_state(username, StateKind::Registration).await
+    }
+
+    async fn start_registration(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        self.start_state(username, StateKind::Registration, &state).await
+    }
+
+    async fn start_password_upgrade(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        self.start_state(username, StateKind::PasswordUpgrade, &state).await
+    }
+
+    async fn get_password_upgrade_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::PasswordUpgrade).await
+    }
+
+    async fn remove_password_upgrade_state(&self, username: &str) -> worker::Result<()> {
+        self.remove_state(username, StateKind::PasswordUpgrade).await
+    }
+
+    async fn start_password_change(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        self.start_state(username, StateKind::PasswordChange, &state).await
+    }
+
+    async fn get_password_change_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::PasswordChange).await
+    }
+
+    async fn remove_password_change_state(&self, username: &str) -> worker::Result<()> {
+        self.remove_state(username, StateKind::PasswordChange).await
+    }
+
+    async fn start_password_reset(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        self.start_state(username, StateKind::PasswordResetRegistration, &state).await
+    }
+
+    async fn get_password_reset_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::PasswordResetRegistration).await
+    }
+
+    async fn remove_password_reset_state(&self, username: &str) -> worker::Result<()> {
+        self.remove_state(username, StateKind::PasswordResetRegistration).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<S: SqlDatabase> LoginData for SqlData<S> {
+    async fn too_many_login_attempts(&self, username: &str) -> worker::Result<bool> {
+        self.too_many_attempts(username, StateKind::Login).await
+    }
+
+    async fn set_login_state(&self, username: &str, state: Vec<u8>) -> worker::Result<()> {
+        self.set_state(username, StateKind::Login, &state).await
+    }
+
+    async fn get_login_state(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::Login).await
+    }
+
+    async fn remove_login_state(&self, username: &str) -> worker::Result<()> {
+        self.remove_state(username, StateKind::Login).await
+    }
+
+    async fn start_login(&self, username: &str, state: Vec<u8>) -> worker::Result<bool> {
+        self.start_state(username, StateKind::Login, &state).await
+    }
+
+    async fn set_login_session(&self, session: &LoginSession, expiration_ttl: u64) -> worker::Result<()> {
+        let now = now_millis();
+        // Rows don't expire by themselves, so the user's expired sessions are cleaned up here
+        self.db.execute("DELETE FROM sessions WHERE username = ?1 AND expires <= ?2", &[json!(session.username), json!(now)]).await?;
+        self.db.execute(
+            "INSERT INTO sessions (id, username, created, last_used, expires) VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (id) DO UPDATE SET last_used = excluded.last_used, expires = excluded.expires",
+            &[json!(session.id), json!(session.username), json!(session.created), json!(session.last_used), json!(now + expiration_ttl * 1000)],
+        ).await?;
+        Ok(())
+    }
+
+    async fn too_many_login_sessions_attempts(&self, username: &str) -> worker::Result<bool> {
+        let now = now_millis();
+        let rows = self.db.query("SELECT COUNT(*) AS count FROM sessions WHERE username = ?1 AND created > ?2 AND expires > ?3", &[json!(username), json!(now.saturating_sub(5 * 1000)), json!(now)]).await?;
+        Ok(rows.first().and_then(|row| row["count"].as_u64()).unwrap_or_default() > 0)
+    }
+
+    async fn get_login_session(&self, session_id: &str) -> worker::Result<Option<LoginSession>> {
+        let rows = self.db.query("SELECT id, username, created, last_used FROM sessions WHERE id = ?1 AND expires > ?2", &[json!(session_id), json!(now_millis())]).await?;
+        match rows.into_iter().next() {
+            Some(row) => Ok(Some(serde_json::from_value(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn list_login_sessions(&self, username: &str) -> worker::Result<Vec<LoginSession>> {
+        let rows = self.db.query("SELECT id, username, created, last_used FROM sessions WHERE username = ?1 AND expires > ?2 ORDER BY created", &[json!(username), json!(now_millis())]).await?;
+        rows.into_iter().map(|row| Ok(serde_json::from_value(row)?)).collect()
+    }
+
+    async fn remove_login_session(&self, session_id: &str, username: &str) -> worker::Result<()> {
+        self.db.execute("DELETE FROM sessions WHERE id = ?1 AND username = ?2", &[json!(session_id), json!(username)]).await?;
+        Ok(())
+    }
+
+    async fn migrate_legacy_login_sessions(&self, _absolute_ttl: u64) -> worker::Result<usize> {
+        // Legacy sessions only ever existed on Workers KV
+        Ok(0)
+    }
+}
+
+#[async_trait(?Send)]
+impl<S: SqlDatabase> PasswordResetData for SqlData<S> {
+    async fn too_many_password_reset_requests(&self, username: &str) -> worker::Result<bool> {
+        self.too_many_attempts(username, StateKind::PasswordReset).await
+    }
+
+    async fn set_password_reset_token(&self, username: &str, token_hash: &[u8]) -> worker::Result<()> {
+        self.set_state(username, StateKind::PasswordReset, token_hash).await
+    }
+
+    async fn get_password_reset_token(&self, username: &str) -> worker::Result<Option<Vec<u8>>> {
+        self.get_state(username, StateKind::PasswordReset).await
+    }
+
+    async fn remove_password_reset_token(&self, username: &str) -> worker::Result<()> {
+        self.remove_state(username, StateKind::PasswordReset).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<S: SqlDatabase> EmailChangeData for SqlData<S> {
+    async fn too_many_email_change_requests(&self, username: &str) -> worker::Result<bool> {
+        self.too_many_attempts(username, StateKind::EmailChange).await
+    }
+
+    async fn set_email_change(&self, username: &str, change: &EmailChange) -> worker::Result<()> {
+        self.set_state(username, StateKind::EmailChange, &serde_json::to_vec(change)?).await
+    }
+
+    async fn get_email_change(&self, username: &str) -> worker::Result<Option<EmailChange>> {
+        self.get_email_change_state(username, StateKind::EmailChange).await
+    }
+
+    async fn remove_email_change(&self, username: &str) -> worker::Result<()> {
+        self.remove_state(username, StateKind::EmailChange).await
+    }
+
+    async fn set_email_revert(&self, username: &str, revert: &EmailChange) -> worker::Result<()> {
+        self.set_state(username, StateKind::EmailRevert, &serde_json::to_vec(revert)?).await
+    }
+
+    async fn get_email_revert(&self, username: &str) -> worker::Result<Option<EmailChange>> {
+        self.get_email_change_state(username, StateKind::EmailRevert).await
+    }
+
+    async fn remove_email_revert(&self, username: &str) -> worker::Result<()> {
+        self.remove_state(username, StateKind::EmailRevert).await
+    }
+}
+
+#[async_trait(?Send)]
+impl<S: SqlDatabase> AuditData for SqlData<S> {
+    async fn record_audit_event(&self, username: &str, event: &str) -> worker::Result<()> {
+        self.db.execute("INSERT INTO audit_events (username, event, created) VALUES (?1, ?2, ?3)", &[json!(username), json!(event), json!(now_millis())]).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::{SqlData, SqliteDatabase, migrations};
+    use crate::{data::{login::{LoginData, LoginSession}, profile::{ProfileData, UserProfile, UserProfileMetadata}, register::RegistrationData}, utils::now_millis};
+
+    async fn data() -> SqlData<SqliteDatabase> {
+        let data = SqlData::with_database(SqliteDatabase::open_in_memory().unwrap());
+        data.migrate().await.unwrap();
+        data
+    }
+
+    fn metadata(email_verified: bool) -> UserProfileMetadata {
+        UserProfileMetadata { v: 0, e: email_verified, l: false, k: 0, h: 0, s: 0 }
+    }
+
+    fn profile(username: &str, mail: &str) -> UserProfile {
+        UserProfile { username: username.to_string(), mail: mail.to_string(), password_file: "file".to_string(), email_verification: "key".to_string(), email_verification_issued: 0 }
+    }
+
+    #[test]
+    fn migrations_are_applied_once() {
+        block_on(async {
+            let data = data().await;
+            assert_eq!(data.migrate().await.unwrap(), migrations::latest_version());
+            assert_eq!(migrations::current_version(&data.db).await.unwrap(), migrations::latest_version());
+        });
+    }
+
+    #[test]
+    fn usernames_and_emails_are_unique() {
+        block_on(async {
+            let data = data().await;
+            assert!(data.create_profile("alice", &profile("alice", "alice@example.com"), &metadata(false)).await.unwrap());
+            assert!(!data.create_profile("alice", &profile("alice", "other@example.com"), &metadata(false)).await.unwrap());
+            assert!(data.create_profile("bob", &profile("bob", "alice@example.com"), &metadata(false)).await.is_err());
+            assert!(data.create_profile("bob", &profile("bob", "bob@example.com"), &metadata(true)).await.unwrap());
+
+            let (alice, metadata) = data.get_profile_by_mail("alice@example.com").await.unwrap().unwrap();
+            assert_eq!(alice.username, "alice");
+            assert!(!metadata.e);
+            assert!(data.profile_already_registered("bob").await.unwrap());
+            assert_eq!(data.list_profiles(10, 0).await.unwrap().len(), 2);
+        });
+    }
+
+    #[test]
+    fn pending_profiles_expire_and_are_promoted() {
+        block_on(async {
+            let data = data().await;
+            assert!(data.create_pending_profile("alice", &profile("alice", "alice@example.com"), &metadata(false), 60).await.unwrap());
+            assert!(!data.create_pending_profile("alice", &profile("alice", "other@example.com"), &metadata(false), 60).await.unwrap());
+            assert!(data.profile_already_registered_waiting_mail_confirm("alice").await.unwrap());
+            assert!(!data.profile_already_registered("alice").await.unwrap());
+
+            assert!(data.promote_pending_profile("alice", &profile("alice", "alice@example.com"), &metadata(true)).await.unwrap());
+            assert!(data.profile_already_registered("alice").await.unwrap());
+            assert!(data.get_pending_profile("alice").await.unwrap().is_none());
+            assert!(!data.create_pending_profile("alice", &profile("alice", "alice@example.com"), &metadata(false), 60).await.unwrap());
+
+            data.save_pending_profile("bob", &profile("bob", "bob@example.com"), &metadata(false), 0).await.unwrap();
+            assert!(!data.profile_already_registered_waiting_mail_confirm("bob").await.unwrap());
+            assert!(data.create_pending_profile("bob", &profile("bob", "bob@example.com"), &metadata(false), 60).await.unwrap());
+        });
+    }
+
+    #[test]
+    fn handshake_states_are_throttled() {
+        block_on(async {
+            let data = data().await;
+            assert!(data.start_registration("alice", vec![1, 2, 3]).await.unwrap());
+            assert!(!data.start_registration("alice", vec![4, 5, 6]).await.unwrap());
+            assert!(data.too_many_registration_attempts("alice").await.unwrap());
+            assert_eq!(data.get_registration_state("alice").await.unwrap(), Some(vec![1, 2, 3]));
+
+            data.remove_registration_state("alice").await.unwrap();
+            assert!(data.start_registration("alice", vec![4, 5, 6]).await.unwrap());
+
+            // a registration, a change, an upgrade and a reset each have their own state
+            assert!(data.start_password_upgrade("alice", vec![7, 8, 9]).await.unwrap());
+            assert!(data.start_password_change("alice", vec![10]).await.unwrap());
+            assert_eq!(data.get_registration_state("alice").await.unwrap(), Some(vec![4, 5, 6]));
+            assert_eq!(data.get_password_upgrade_state("alice").await.unwrap(), Some(vec![7, 8, 9]));
+            assert!(data.start_password_reset("alice", vec![11]).await.unwrap());
+            assert_eq!(data.get_password_change_state("alice").await.unwrap(), Some(vec![10]));
+            assert_eq!(data.get_password_reset_state("alice").await.unwrap(), Some(vec![11]));
+        });
+    }
+
+    #[test]
+    fn login_sessions_expire() {
+        block_on(async {
+            let data = data().await;
+            let now = now_millis();
+            let session = LoginSession { id: "s1".to_string(), username: "alice".to_string(), created: now, last_used: now };
+            data.set_login_session(&session, 60).await.unwrap();
+            data.set_login_session(&LoginSession { id: "s2".to_string(), ..session.clone() }, 0).await.unwrap();
+
+            assert_eq!(data.list_login_sessions("alice").await.unwrap().len(), 1);
+            assert!(data.get_login_session("s2").await.unwrap().is_none());
+            data.remove_login_session("s1", "alice").await.unwrap();
+            assert!(data.get_login_session("s1").await.unwrap().is_none());
+        });
+    }
+}
@@ -0,0 +1,68 @@
+use async_trait::async_trait;
+use rusqlite::{types::{Value as SqliteValue, ValueRef}, params_from_iter, Connection};
+use serde_json::{Map, Value};
+
+use super::SqlDatabase;
+
+// SQLite database for tests and running the data layer off Cloudflare, D1 uses the same dialect
+pub struct SqliteDatabase {
+    connection: Connection,
+}
+
+impl SqliteDatabase {
+    pub fn open(path: &str) -> worker::Result<Self> {
+        Ok(Self { connection: Connection::open(path).map_err(|err| format!("{}",err))? })
+    }
+
+    pub fn open_in_memory() -> worker::Result<Self> {
+        Ok(Self { connection: Connection::open_in_memory().map_err(|err| format!("{}",err))? })
+    }
+}
+
+fn to_sqlite(value: &Value) -> SqliteValue {
+    match value {
+        Value::Null => SqliteValue::Null,
+        Value::Bool(value) => SqliteValue::Integer(*value as i64),
+        Value::Number(number) => number.as_i64().map_or_else(|| SqliteValue::Real(number.as_f64().unwrap_or_default()), SqliteValue::Integer),
+        Value::String(value) => SqliteValue::Text(value.clone()),
+        value => SqliteValue::Text(value.to_string()),
+    }
+}
+
+fn to_json(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(value) => Value::from(value),
+        ValueRef::Real(value) => Value::from(value),
+        ValueRef::Text(value) => Value::from(String::from_utf8_lossy(value).into_owned()),
+        ValueRef::Blob(value) => Value::from(base64::encode(value)),
+    }
+}
+
+#[async_trait(?Send)]
+impl SqlDatabase for SqliteDatabase {
+    async fn execute(&self, sql: &str, params: &[Value]) -> worker::Result<u64> {
+        let changes = self.connection.execute(sql, params_from_iter(params.iter().map(to_sqlite))).map_err(|err| format!("{}",err))?;
+        Ok(changes as u64)
+    }
+
+    async fn query(&self, sql: &str, params: &[Value]) -> worker::Result<Vec<Value>> {
+        let mut statement = self.connection.prepare(sql).map_err(|err| format!("{}",err))?;
+        let columns = statement.column_names().into_iter().map(String::from).collect::<Vec<_>>();
+        let mut rows = statement.query(params_from_iter(params.iter().map(to_sqlite))).map_err(|err| format!("{}",err))?;
+
+        let mut results = vec![];
+        while let Some(row) = rows.next().map_err(|err| format!("{}",err))? {
+            let mut object = Map::new();
+            for (index, column) in columns.iter().enumerate() {
+                object.insert(column.clone(), to_json(row.get_ref(index).map_err(|err| format!("{}",err))?));
+            }
+            results.push(Value::Object(object));
+        }
+        Ok(results)
+    }
+
+    async fn execute_batch(&self, sql: &str) -> worker::Result<()> {
+        Ok(self.connection.execute_batch(sql).map_err(|err| format!("{}",err))?)
+    }
+}
@@ -0,0 +1,66 @@
+use hmac::{Hmac, Mac, NewMac};
+use opaque_ke::rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+
+pub const DEVICE_COOKIE: &str = "DEVICE";
+const DEVICE_COOKIE_KEY: &str = "DEVICE_COOKIE_KEY";
+
+// A year, the cookie is renewed on every login
+const DEVICE_COOKIE_MAX_AGE: u64 = 365 * 24 * 60 * 60;
+
+// Browsers that logged in to an account before keep a `DEVICE` cookie, a random id and a MAC of
+// it with the username, so logins from new devices can be told to the user without storing the
+// devices. The cookie of another account on the same browser doesn't count.
+fn device_mac(key: &[u8], username: &str, id: &str) -> worker::Result<Hmac<Sha256>> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).map_err(|err| format!("{}",err))?;
+    mac.update(b"device");
+    mac.update(&(username.len() as u64).to_be_bytes());
+    mac.update(username.as_bytes());
+    mac.update(id.as_bytes());
+    Ok(mac)
+}
+
+// MAC key of the cookies, the base64 `DEVICE_COOKIE_KEY` secret. Without it devices aren't tracked
+// and no new device notices are sent.
+pub fn cookie_key<D>(ctx: &worker::RouteContext<D>) -> worker::Result<Option<Vec<u8>>> {
+    match ctx.secret(DEVICE_COOKIE_KEY) {
+        Ok(key) => Ok(Some(base64::decode(key.to_string()).map_err(|err| format!("{}",err))?)),
+        Err(_) => Ok(None),
+    }
+}
+
+pub fn known_device(cookie: Option<&str>, key: &[u8], username: &str) -> bool {
+    let (id, tag) = match cookie.and_then(|cookie| cookie.split_once('.')) {
+        Some(parts) => parts,
+        None => return false,
+    };
+    match base64::decode_config(tag, base64::URL_SAFE_NO_PAD) {
+        Ok(tag) => device_mac(key, username, id).is_ok_and(|mac| mac.verify(&tag).is_ok()),
+        Err(_) => false,
+    }
+}
+
+pub fn set_cookie_header(key: &[u8], username: &str) -> worker::Result<String> {
+    let mut id = [0u8; 16];
+    OsRng.fill_bytes(&mut id);
+    let id = base64::encode_config(id, base64::URL_SAFE_NO_PAD);
+    let tag = base64::encode_config(device_mac(key, username, &id)?.finalize().into_bytes(), base64::URL_SAFE_NO_PAD);
+    Ok(format!("{}={}.{}; Path=/; HttpOnly; Secure; SameSite=Strict; Max-Age={}", DEVICE_COOKIE, id, tag, DEVICE_COOKIE_MAX_AGE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{known_device, set_cookie_header, DEVICE_COOKIE};
+
+    #[test]
+    fn device_cookie_is_bound_to_the_user() {
+        let header = set_cookie_header(b"key", "alice").unwrap();
+        let cookie = header.strip_prefix(&format!("{}=", DEVICE_COOKIE)).and_then(|header| header.split(';').next());
+
+        assert!(known_device(cookie, b"key", "alice"));
+        assert!(!known_device(cookie, b"key", "bob"));
+        assert!(!known_device(cookie, b"other key", "alice"));
+        assert!(!known_device(Some("forged.AAAA"), b"key", "alice"));
+        assert!(!known_device(None, b"key", "alice"));
+    }
+}
@@ -0,0 +1,33 @@
+use std::cell::RefCell;
+
+use async_trait::async_trait;
+
+use super::{Email, EmailSender};
+
+// Keeps the emails instead of sending them, for tests and local development where the links can
+// be taken from the worker logs
+#[derive(Default)]
+pub struct CaptureSender {
+    sent: RefCell<Vec<Email>>,
+}
+
+impl CaptureSender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg(test)]
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.borrow().clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl EmailSender for CaptureSender {
+    async fn send(&self, email: &Email) -> worker::Result<()> {
+        #[cfg(target_arch = "wasm32")]
+        worker::console_log!("email to '{}', '{}': {}", email.to, email.subject, email.text);
+        self.sent.borrow_mut().push(email.clone());
+        Ok(())
+    }
+}
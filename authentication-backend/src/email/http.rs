@@ -0,0 +1,34 @@
+use async_trait::async_trait;
+use serde_json::json;
+
+use super::{post_json, Email, EmailSender, Mailbox};
+
+// Providers with a plain JSON send endpoint, or a relay in front of an SMTP server since Workers
+// can't open SMTP connections. The key is sent as a bearer token.
+pub struct HttpSender {
+    url: String,
+    api_key: String,
+    from: Mailbox,
+}
+
+impl HttpSender {
+    pub fn new(url: String, api_key: String, from: Mailbox) -> Self {
+        Self { url, api_key, from }
+    }
+}
+
+#[async_trait(?Send)]
+impl EmailSender for HttpSender {
+    async fn send(&self, email: &Email) -> worker::Result<()> {
+        let mut headers = worker::Headers::new();
+        headers.set("Authorization", &format!("Bearer {}", self.api_key))?;
+        let body = json!({
+            "from": self.from.to_string(),
+            "to": email.to,
+            "subject": email.subject,
+            "text": email.text,
+            "html": email.html,
+        });
+        post_json(&self.url, headers, &body).await
+    }
+}
@@ -0,0 +1,111 @@
+pub mod capture;
+pub mod http;
+pub mod sendinblue;
+pub mod template;
+
+use async_trait::async_trait;
+use serde_json::Value;
+
+pub use capture::CaptureSender;
+pub use http::HttpSender;
+pub use sendinblue::SendinblueSender;
+pub use template::Template;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mailbox {
+    pub email: String,
+    pub name: Option<String>,
+}
+
+// Formatted as an address header, `Name <email>`
+impl std::fmt::Display for Mailbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} <{}>", name, self.email),
+            None => write!(f, "{}", self.email),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+// Delivers the emails of the account flows, so the provider can be changed without touching them
+#[async_trait(?Send)]
+pub trait EmailSender {
+    async fn send(&self, email: &Email) -> worker::Result<()>;
+}
+
+// Sender picked by the `EMAIL_PROVIDER` var: "sendinblue" (the default), "http" for a provider
+// taking a JSON body at `EMAIL_HTTP_URL`, or "capture" to only log emails when developing. The
+// sending address is the `EMAIL_SENDER` var, named by the optional `EMAIL_SENDER_NAME`, and
+// providers authenticate with the `EMAILER_KEY` secret.
+pub fn sender<D>(ctx: &worker::RouteContext<D>) -> worker::Result<Box<dyn EmailSender>> {
+    let provider = ctx.var("EMAIL_PROVIDER").map_or_else(|_| String::new(), |provider| provider.to_string());
+    if provider == "capture" {
+        return Ok(Box::new(CaptureSender::new()));
+    }
+
+    let from = Mailbox {
+        email: ctx.var("EMAIL_SENDER")?.to_string(),
+        name: ctx.var("EMAIL_SENDER_NAME").ok().map(|name| name.to_string()),
+    };
+    let api_key = ctx.secret("EMAILER_KEY")?.to_string();
+    match provider.as_str() {
+        "http" => Ok(Box::new(HttpSender::new(ctx.var("EMAIL_HTTP_URL")?.to_string(), api_key, from))),
+        "" | "sendinblue" => Ok(Box::new(SendinblueSender::new(api_key, from))),
+        provider => Err(format!("Unknown email provider {}", provider).into()),
+    }
+}
+
+// Sends the account emails in the language of the request, `EMAIL_DEFAULT_LOCALE` if it has no
+// templates, with links to the worker at the `PUBLIC_URL` var and to the web application at the
+// `FRONTEND_URL` var
+pub struct Mailer<'a> {
+    pub sender: &'a dyn EmailSender,
+    pub locale: &'static str,
+    pub public_url: String,
+    pub frontend_url: String,
+}
+
+impl<'a> Mailer<'a> {
+    pub fn new<D>(sender: &'a dyn EmailSender, ctx: &worker::RouteContext<D>, req: &worker::Request) -> worker::Result<Self> {
+        let default_locale = ctx.var("EMAIL_DEFAULT_LOCALE").ok()
+            .and_then(|locale| template::LOCALES.iter().find(|known| **known == locale.to_string()).copied())
+            .unwrap_or(template::DEFAULT_LOCALE);
+        let accept_language = req.headers().get("Accept-Language")?;
+        Ok(Self {
+            sender,
+            locale: template::negotiate_locale(accept_language.as_deref(), default_locale),
+            public_url: ctx.var("PUBLIC_URL")?.to_string().trim_end_matches('/').to_string(),
+            frontend_url: ctx.var("FRONTEND_URL")?.to_string().trim_end_matches('/').to_string(),
+        })
+    }
+
+    pub async fn send(&self, to: &str, template: Template, variables: &[(&str, &str)]) -> worker::Result<()> {
+        self.sender.send(&template::render(template, self.locale, to, variables)).await
+    }
+}
+
+// POSTs a JSON body to a provider API, failing unless it answers with a 2xx status
+async fn post_json(url: &str, mut headers: worker::Headers, body: &Value) -> worker::Result<()> {
+    headers.set("Content-Type", "application/json")?;
+    headers.set("Accept", "application/json")?;
+
+    let mut req_init = worker::RequestInit::new();
+    req_init.with_method(worker::Method::Post).with_headers(headers).with_body(Some(worker::wasm_bindgen::JsValue::from_str(&body.to_string())));
+    let req = worker::Request::new_with_init(url, &req_init)?;
+
+    let mut response = worker::Fetch::Request(req).send().await?;
+    let status = response.status_code();
+    if !(200..300).contains(&status) {
+        let text = response.text().await?;
+        return Err(format!("Email provider answered {}: {}", status, text).into());
+    }
+    Ok(())
+}
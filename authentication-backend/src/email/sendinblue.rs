@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use serde_json::json;
+
+use super::{post_json, Email, EmailSender, Mailbox};
+
+const SENDINBLUE_URL: &str = "https://api.sendinblue.com/v3/smtp/email";
+
+pub struct SendinblueSender {
+    api_key: String,
+    from: Mailbox,
+}
+
+impl SendinblueSender {
+    pub fn new(api_key: String, from: Mailbox) -> Self {
+        Self { api_key, from }
+    }
+}
+
+#[async_trait(?Send)]
+impl EmailSender for SendinblueSender {
+    async fn send(&self, email: &Email) -> worker::Result<()> {
+        let mut headers = worker::Headers::new();
+        headers.set("api-key", &self.api_key)?;
+        let body = json!({
+            "sender": { "email": self.from.email, "name": self.from.name },
+            "to": [ { "email": email.to } ],
+            "subject": email.subject,
+            "textContent": email.text,
+            "htmlContent": email.html,
+        });
+        post_json(SENDINBLUE_URL, headers, &body).await
+    }
+}
@@ -0,0 +1,135 @@
+use super::Email;
+
+// Emails of the account flows. Every template has a text and an HTML variant per locale, in
+// `templates/<locale>/<name>.txt` and `.html`; the first line of the text variant is the subject.
+// `{{name}}` placeholders are replaced by the variables, HTML escaped in the HTML variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Template {
+    // variables: username, link, expiry (hours)
+    Confirmation,
+    // variables: username, link, expiry (minutes)
+    PasswordReset,
+    // variables: username, device, link
+    NewDeviceLogin,
+    // variables: username, link
+    PasswordChanged,
+    // variables: username, link
+    AccountDeleted,
+    // to the new address; variables: username, mail (the new one), link, expiry (hours)
+    EmailChangeConfirmation,
+    // to the old address; variables: username, mail (the new one), link
+    EmailChangeNotice,
+    // to the old address once changed; variables: username, mail (the new one), link, expiry (days)
+    EmailChangeRevert,
+}
+
+pub const LOCALES: &[&str] = &["en", "pt"];
+pub const DEFAULT_LOCALE: &str = "en";
+
+macro_rules! template_files {
+    ($locale:literal, $template:expr) => {
+        match $template {
+            Template::Confirmation => (include_str!(concat!("templates/", $locale, "/confirmation.txt")), include_str!(concat!("templates/", $locale, "/confirmation.html"))),
+            Template::PasswordReset => (include_str!(concat!("templates/", $locale, "/password_reset.txt")), include_str!(concat!("templates/", $locale, "/password_reset.html"))),
+            Template::NewDeviceLogin => (include_str!(concat!("templates/", $locale, "/new_device_login.txt")), include_str!(concat!("templates/", $locale, "/new_device_login.html"))),
+            Template::PasswordChanged => (include_str!(concat!("templates/", $locale, "/password_changed.txt")), include_str!(concat!("templates/", $locale, "/password_changed.html"))),
+            Template::AccountDeleted => (include_str!(concat!("templates/", $locale, "/account_deleted.txt")), include_str!(concat!("templates/", $locale, "/account_deleted.html"))),
+            Template::EmailChangeConfirmation => (include_str!(concat!("templates/", $locale, "/email_change_confirmation.txt")), include_str!(concat!("templates/", $locale, "/email_change_confirmation.html"))),
+            Template::EmailChangeNotice => (include_str!(concat!("templates/", $locale, "/email_change_notice.txt")), include_str!(concat!("templates/", $locale, "/email_change_notice.html"))),
+            Template::EmailChangeRevert => (include_str!(concat!("templates/", $locale, "/email_change_revert.txt")), include_str!(concat!("templates/", $locale, "/email_change_revert.html"))),
+        }
+    };
+}
+
+fn sources(template: Template, locale: &str) -> (&'static str, &'static str) {
+    match locale {
+        "pt" => template_files!("pt", template),
+        _ => template_files!("en", template),
+    }
+}
+
+pub fn render(template: Template, locale: &str, to: &str, variables: &[(&str, &str)]) -> Email {
+    let (text, html) = sources(template, locale);
+    let (subject, text) = text.split_once('\n').unwrap_or((text, ""));
+    Email {
+        to: to.to_string(),
+        subject: fill(subject.trim(), variables, false),
+        text: fill(text.trim_start(), variables, false),
+        html: fill(html, variables, true),
+    }
+}
+
+fn fill(source: &str, variables: &[(&str, &str)], html: bool) -> String {
+    variables.iter().fold(source.to_string(), |output, (name, value)| {
+        let value = if html { escape_html(value) } else { value.to_string() };
+        output.replace(&format!("{{{{{}}}}}", name), &value)
+    })
+}
+
+fn escape_html(value: &str) -> String {
+    value.chars().fold(String::with_capacity(value.len()), |mut output, c| {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&#39;"),
+            c => output.push(c),
+        }
+        output
+    })
+}
+
+// The locale with templates that ranks highest in an `Accept-Language` header, `default` if none
+pub fn negotiate_locale(accept_language: Option<&str>, default: &'static str) -> &'static str {
+    let mut languages: Vec<(&str, f32)> = accept_language
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|language| {
+            let mut parts = language.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|part| part.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.parse().ok())?;
+            Some((tag, quality))
+        })
+        .collect();
+    // stable, so languages of the same quality keep the header order
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages
+        .into_iter()
+        .filter(|(_, quality)| *quality > 0.0)
+        .find_map(|(tag, _)| {
+            let primary = tag.split('-').next().unwrap_or_default();
+            LOCALES.iter().find(|locale| locale.eq_ignore_ascii_case(primary)).copied()
+        })
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{negotiate_locale, render, Template, LOCALES};
+
+    #[test]
+    fn every_template_fills_its_variables() {
+        let templates = [Template::Confirmation, Template::PasswordReset, Template::NewDeviceLogin, Template::PasswordChanged, Template::AccountDeleted, Template::EmailChangeConfirmation, Template::EmailChangeNotice, Template::EmailChangeRevert];
+        let variables = [("username", "alice"), ("link", "https://example.com/?a=1&b=2"), ("expiry", "60"), ("device", "<script>"), ("mail", "new@example.com")];
+        for locale in LOCALES {
+            for template in templates {
+                let email = render(template, locale, "alice@example.com", &variables);
+                assert!(!email.subject.is_empty() && !email.subject.contains("{{"));
+                assert!(email.text.contains("alice") && !email.text.contains("{{"));
+                assert!(email.html.contains("a=1&amp;b=2") && !email.html.contains("{{") && !email.html.contains("<script>"));
+            }
+        }
+    }
+
+    #[test]
+    fn locale_follows_accept_language() {
+        assert_eq!(negotiate_locale(Some("pt-PT,pt;q=0.9,en;q=0.8"), "en"), "pt");
+        assert_eq!(negotiate_locale(Some("fr-FR, en;q=0.5, pt;q=0.7"), "en"), "pt");
+        assert_eq!(negotiate_locale(Some("fr"), "pt"), "pt");
+        assert_eq!(negotiate_locale(Some("pt;q=0"), "en"), "en");
+        assert_eq!(negotiate_locale(None, "en"), "en");
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use authentication_api::{ErrorCode, ErrorResponse};
+
+// Every failure a handler answers with. Each one has a fixed HTTP status, `ErrorCode` and message,
+// and is sent as an `ErrorResponse` JSON body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    BadRequest,
+    // field name to the reason it was rejected
+    InvalidFields(HashMap<String, String>),
+    InvalidUsername,
+    UnsupportedApiVersion,
+    UnsupportedCipherSuite,
+    UserAlreadyRegistered,
+    RegistrationPendingEmailConfirmation,
+    TooManyAttempts,
+    NoRegistrationState,
+    NoLoginState,
+    PasswordFileUpToDate,
+    InvalidResetToken,
+    InvalidConfirmationKey,
+    UnknownUser,
+    EmailNotVerified,
+    EmailAlreadyVerified,
+    EmailAlreadyInUse,
+    AccountLocked,
+    InvalidKeyConfirmation,
+    Unauthorized,
+    SessionNotFound,
+    ServerKeyUnavailable,
+    // a handler failed, the cause is only logged
+    Internal,
+}
+
+impl ApiError {
+    #[must_use]
+    pub fn status(&self) -> u16 {
+        match self {
+            ApiError::EmailNotVerified => 403,
+            ApiError::AccountLocked | ApiError::InvalidKeyConfirmation | ApiError::Unauthorized => 401,
+            ApiError::SessionNotFound => 404,
+            ApiError::UserAlreadyRegistered | ApiError::EmailAlreadyVerified | ApiError::EmailAlreadyInUse => 409,
+            ApiError::TooManyAttempts => 429,
+            ApiError::ServerKeyUnavailable | ApiError::Internal => 500,
+            _ => 400,
+        }
+    }
+
+    #[must_use]
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            ApiError::BadRequest => ErrorCode::BadRequest,
+            ApiError::InvalidFields(_) => ErrorCode::InvalidFields,
+            ApiError::InvalidUsername => ErrorCode::InvalidUsername,
+            ApiError::UnsupportedApiVersion => ErrorCode::UnsupportedApiVersion,
+            ApiError::UnsupportedCipherSuite => ErrorCode::UnsupportedCipherSuite,
+            ApiError::UserAlreadyRegistered => ErrorCode::UserAlreadyRegistered,
+            ApiError::RegistrationPendingEmailConfirmation => ErrorCode::RegistrationPendingEmailConfirmation,
+            ApiError::TooManyAttempts => ErrorCode::TooManyAttempts,
+            ApiError::NoRegistrationState => ErrorCode::NoRegistrationState,
+            ApiError::NoLoginState => ErrorCode::NoLoginState,
+            ApiError::PasswordFileUpToDate => ErrorCode::PasswordFileUpToDate,
+            ApiError::InvalidResetToken => ErrorCode::InvalidResetToken,
+            ApiError::InvalidConfirmationKey => ErrorCode::InvalidConfirmationKey,
+            ApiError::UnknownUser => ErrorCode::UnknownUser,
+            ApiError::EmailNotVerified => ErrorCode::EmailNotVerified,
+            ApiError::EmailAlreadyVerified => ErrorCode::EmailAlreadyVerified,
+            ApiError::EmailAlreadyInUse => ErrorCode::EmailAlreadyInUse,
+            ApiError::AccountLocked => ErrorCode::AccountLocked,
+            ApiError::InvalidKeyConfirmation => ErrorCode::InvalidKeyConfirmation,
+            ApiError::Unauthorized => ErrorCode::Unauthorized,
+            ApiError::SessionNotFound => ErrorCode::SessionNotFound,
+            ApiError::ServerKeyUnavailable => ErrorCode::ServerKeyUnavailable,
+            ApiError::Internal => ErrorCode::Internal,
+        }
+    }
+
+    #[must_use]
+    pub fn message(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest => "Bad request",
+            ApiError::InvalidFields(_) => "Some field(s) have invalid data",
+            ApiError::InvalidUsername => "Invalid username",
+            ApiError::UnsupportedApiVersion => "Unsupported API version",
+            ApiError::UnsupportedCipherSuite => "Unsupported cipher suite",
+            ApiError::UserAlreadyRegistered => "User already registered",
+            ApiError::RegistrationPendingEmailConfirmation => "User already registered, missing confirming email",
+            ApiError::TooManyAttempts => "Too many attempts, try again later",
+            ApiError::NoRegistrationState => "No registration in progress",
+            ApiError::NoLoginState => "No login in progress",
+            ApiError::PasswordFileUpToDate => "Password file is already up to date",
+            ApiError::InvalidResetToken => "Invalid or expired reset token",
+            ApiError::InvalidConfirmationKey => "Invalid or expired confirmation key",
+            ApiError::UnknownUser => "Username does not exist",
+            ApiError::EmailNotVerified => "Email not verified",
+            ApiError::EmailAlreadyVerified => "Email already verified",
+            ApiError::EmailAlreadyInUse => "Email already used by another account",
+            ApiError::AccountLocked => "Account locked",
+            ApiError::InvalidKeyConfirmation => "Invalid key confirmation",
+            ApiError::Unauthorized => "Unauthorized",
+            ApiError::SessionNotFound => "Session not found",
+            ApiError::ServerKeyUnavailable => "Server key not available",
+            ApiError::Internal => "Internal server error",
+        }
+    }
+
+    #[must_use]
+    pub fn body(self) -> ErrorResponse {
+        let code = self.code();
+        let message = self.message().to_string();
+        let fields = match self {
+            ApiError::InvalidFields(fields) => fields,
+            _ => HashMap::new(),
+        };
+        ErrorResponse { code, message, fields }
+    }
+
+    pub fn response(self) -> worker::Result<worker::Response> {
+        let status = self.status();
+        Ok(worker::Response::from_json(&self.body())?.with_status(status))
+    }
+}
+
+// Unwraps the result of decoding or processing a message sent by the client. A failure there is
+// the client's fault, so it is logged and answered with `BadRequest` rather than as an internal
+// error.
+macro_rules! client_input {
+    ($result:expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(err) => {
+                worker::console_log!("Rejected client input: {}", err);
+                return $crate::error::ApiError::BadRequest.response();
+            }
+        }
+    };
+}
+pub(crate) use client_input;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use authentication_api::ErrorCode;
+
+    use super::ApiError;
+
+    #[test]
+    fn only_invalid_fields_carry_fields() {
+        let fields = HashMap::from([("mail".to_string(), "Invalid email address".to_string())]);
+        let body = ApiError::InvalidFields(fields.clone()).body();
+        assert_eq!((body.code, body.fields), (ErrorCode::InvalidFields, fields));
+
+        let body = ApiError::TooManyAttempts.body();
+        assert_eq!(body.code, ErrorCode::TooManyAttempts);
+        assert!(body.fields.is_empty());
+        assert_eq!(ApiError::TooManyAttempts.status(), 429);
+    }
+}
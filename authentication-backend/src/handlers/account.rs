@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use worker::console_log;
+
+use authentication_api::EmailChangeRequest;
+
+use crate::{data::{self, email_change::EmailChange}, email::Mailer, error::ApiError, session::{self, SessionConfig}};
+
+// With the `EMAIL_CHANGE_REVERT` var set to "true", the old address gets a link to undo a change
+fn revert_enabled(ctx: &worker::RouteContext<worker::Env>) -> bool {
+    ctx.var("EMAIL_CHANGE_REVERT").is_ok_and(|value| value.to_string() == "true")
+}
+
+fn key_hash(key: &str) -> worker::Result<Vec<u8>> {
+    let key = base64::decode_config(key, base64::URL_SAFE).map_err(|err| format!("{}",err))?;
+    Ok(Sha256::digest(&key).to_vec())
+}
+
+fn key_matches(change: &EmailChange, key: &str) -> bool {
+    match base64::decode_config(key, base64::URL_SAFE) {
+        Ok(key) => bool::from(Sha256::digest(&key).as_slice().ct_eq(&change.key_hash)),
+        Err(_) => false,
+    }
+}
+
+// The username and key of a link sent by email
+fn link_params(req: &worker::Request, ctx: &worker::RouteContext<worker::Env>) -> worker::Result<Option<(String, String)>> {
+    let username = match ctx.param("username") {
+        Some(username) if crate::utils::validate_username(username) => username.to_string(),
+        _ => return Ok(None),
+    };
+    let key = req.url()?.query_pairs().find(|(key, _)| key == "k").map_or(String::new(), |(_, value)| value.to_string());
+    Ok(Some((username, key)))
+}
+
+pub async fn email_change_handler(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    let data = data::store(&ctx)?;
+    let config = SessionConfig::new(&ctx);
+
+    let authenticated = match session::authenticate(&req, data.as_ref(), &config).await? {
+        Some(authenticated) => authenticated,
+        None => return ApiError::Unauthorized.response(),
+    };
+
+    if let Ok(mut values) = req.json::<EmailChangeRequest>().await {
+        let username = &authenticated.profile.username;
+        values.mail = crate::utils::normalize_mail(&values.mail);
+
+        if !crate::utils::validate_mail(&values.mail) || values.mail == crate::utils::normalize_mail(&authenticated.profile.mail) {
+            let fields = HashMap::from([("mail".to_string(), "Invalid email address, please enter a valid email address other than the current one.".to_string())]);
+            return ApiError::InvalidFields(fields).response();
+        }
+
+        if data.mail_owner(&values.mail).await?.is_some() {
+            return ApiError::EmailAlreadyInUse.response();
+        }
+
+        if data.too_many_email_change_requests(username).await? {
+            return ApiError::TooManyAttempts.response();
+        }
+
+        // The key is stored before it's sent, a link that can't be confirmed never goes out
+        let key = crate::confirmation_email::generate_key();
+        data.set_email_change(username, &EmailChange { mail: values.mail.clone(), key_hash: key_hash(&key)? }).await?;
+
+        let sender = crate::email::sender(&ctx)?;
+        let mailer = Mailer::new(sender.as_ref(), &ctx, &req)?;
+        if let Err(err) = crate::confirmation_email::send_email_change(&mailer, username, &values.mail, &key).await {
+            data.remove_email_change(username).await?;
+            return Err(err);
+        }
+        crate::confirmation_email::send_email_change_notice(&mailer, username, &authenticated.profile.mail, &values.mail).await?;
+        data.record_audit_event(username, "email_change_requested").await?;
+
+        return worker::Response::ok("");
+    }
+    ApiError::BadRequest.response()
+}
+
+pub async fn email_confirm_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    let (username, key) = match link_params(&req, &ctx)? {
+        Some(params) => params,
+        None => return ApiError::BadRequest.response(),
+    };
+
+    let data = data::store(&ctx)?;
+    let change = match data.get_email_change(&username).await? {
+        Some(change) if key_matches(&change, &key) => change,
+        _ => return ApiError::InvalidConfirmationKey.response(),
+    };
+    let (mut profile, metadata) = match data.get_profile(&username).await? {
+        Some(profile) => profile,
+        None => return ApiError::InvalidConfirmationKey.response(),
+    };
+    if metadata.l {
+        return ApiError::AccountLocked.response();
+    }
+    // Another account may have taken the address since the change was asked for
+    if data.mail_owner(&change.mail).await?.is_some() {
+        return ApiError::EmailAlreadyInUse.response();
+    }
+
+    let old_mail = std::mem::replace(&mut profile.mail, change.mail);
+    data.save_profile(&username, &profile, &metadata).await?;
+    data.remove_email_change(&username).await?;
+    data.record_audit_event(&username, "email_changed").await?;
+
+    if revert_enabled(&ctx) {
+        let key = crate::confirmation_email::generate_key();
+        data.set_email_revert(&username, &EmailChange { mail: old_mail.clone(), key_hash: key_hash(&key)? }).await?;
+
+        let sender = crate::email::sender(&ctx)?;
+        let mailer = Mailer::new(sender.as_ref(), &ctx, &req)?;
+        if let Err(err) = crate::confirmation_email::send_email_change_revert(&mailer, &username, &old_mail, &profile.mail, &key).await {
+            // the change is already made, it stays without a way back
+            data.remove_email_revert(&username).await?;
+            console_log!("Email change revert link failed: {}", err);
+        }
+    }
+
+    worker::Response::ok("")
+}
+
+// Puts the previous address back and logs every session out, the change is taken as an attack
+pub async fn email_revert_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    let (username, key) = match link_params(&req, &ctx)? {
+        Some(params) => params,
+        None => return ApiError::BadRequest.response(),
+    };
+
+    let data = data::store(&ctx)?;
+    let revert = match data.get_email_revert(&username).await? {
+        Some(revert) if key_matches(&revert, &key) => revert,
+        _ => return ApiError::InvalidConfirmationKey.response(),
+    };
+    let (mut profile, metadata) = match data.get_profile(&username).await? {
+        Some(profile) => profile,
+        None => return ApiError::InvalidConfirmationKey.response(),
+    };
+
+    if data.mail_owner(&revert.mail).await?.is_some_and(|owner| owner != username) {
+        return ApiError::EmailAlreadyInUse.response();
+    }
+
+    profile.mail = revert.mail;
+    data.save_profile(&username, &profile, &metadata).await?;
+    data.remove_email_revert(&username).await?;
+    data.remove_email_change(&username).await?;
+    for session in data.list_login_sessions(&username).await? {
+        data.remove_login_session(&session.id, &username).await?;
+    }
+    data.record_audit_event(&username, "email_change_reverted").await?;
+
+    worker::Response::ok("")
+}
@@ -0,0 +1,55 @@
+use subtle::ConstantTimeEq;
+
+use authentication_api::{MigrationResponse, SchemaMigrationResponse};
+
+use crate::{data, error::ApiError, session::SessionConfig};
+
+// Admin endpoints are only reachable with the `ADMIN_KEY` secret in the `X-Admin-Key` header
+fn is_admin<D>(req: &worker::Request, ctx: &worker::RouteContext<D>) -> worker::Result<bool> {
+    let admin_key = match ctx.secret("ADMIN_KEY") {
+        Ok(admin_key) => admin_key.to_string(),
+        Err(_) => return Ok(false),
+    };
+    if let Some(key) = req.headers().get("X-Admin-Key")? {
+        return Ok(!admin_key.is_empty() && bool::from(key.as_bytes().ct_eq(admin_key.as_bytes())));
+    }
+    Ok(false)
+}
+
+pub async fn migrate_sessions_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    if !is_admin(&req, &ctx)? {
+        return ApiError::Unauthorized.response();
+    }
+
+    let data = data::store(&ctx)?;
+    let config = SessionConfig::new(&ctx);
+
+    let migrated = data.migrate_legacy_login_sessions(config.absolute_ttl).await?;
+    worker::Response::from_json(&MigrationResponse { migrated })
+}
+
+// Indexes the emails of Workers KV profiles saved before the index, D1 needs none and Durable
+// Objects can't be listed so the call fails
+pub async fn migrate_mail_index_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    if !is_admin(&req, &ctx)? {
+        return ApiError::Unauthorized.response();
+    }
+
+    let data = data::store(&ctx)?;
+    let migrated = data.index_profile_mails().await?;
+    worker::Response::from_json(&MigrationResponse { migrated })
+}
+
+// Brings the D1 schema up to date, to be called after deploying a version with new migrations
+pub async fn migrate_schema_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    if !is_admin(&req, &ctx)? {
+        return ApiError::Unauthorized.response();
+    }
+
+    let data = data::sql::SqlData::new(ctx.data())?;
+    let version = data.migrate().await?;
+    worker::Response::from_json(&SchemaMigrationResponse { version })
+}
@@ -1,113 +1,160 @@
-use opaque_ke::keypair::KeyPair;
-use serde::Deserialize;
+use authentication_rs_lib::key_confirmation::{confirmation_tag, verify_confirmation_tag, CLIENT_CONFIRMATION_LABEL, SERVER_CONFIRMATION_LABEL};
 use worker::console_log;
 
-use crate::{data::{AuthenticationData, login::LoginData, profile::{ProfileData, UserProfileMetadata}}, utils::{unwrap_abort}};
+use authentication_api::{LoginFinishRequest, LoginFinishResponse, LoginRequest};
 
-#[derive(Deserialize)]
-struct LoginRequest {
-    username: String,
-    request: String,
-}
+use authentication_rs_lib::cipher_suite;
+
+use crate::{data::{self, login::LoginSession, profile::UserProfileMetadata}, device, email::Mailer, error::{client_input, ApiError}, keys::KeyRing, opaque, session::{SessionConfig, SessionToken}};
 
-pub async fn start_handler<D>(mut req: worker::Request, ctx: worker::RouteContext<D>) -> worker::Result<worker::Response>
+pub async fn start_handler(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
 {
     if let Ok(values) = req.json::<LoginRequest>().await {
 
         if !crate::utils::validate_username(&values.username) {
-            return worker::Response::error("Invalid username", 400);
+            return ApiError::InvalidUsername.response();
         }
 
-        let data = AuthenticationData::new(&ctx);
+        let data = data::store(&ctx)?;
         
         if data.too_many_login_attempts(&values.username).await?  {           
-            return worker::Response::error("Too many login retries", 400);
+            return ApiError::TooManyAttempts.response();
         }
 
         if data.too_many_login_sessions_attempts(&values.username).await?  {           
-            return worker::Response::error("Too many login session tries", 400);
-        }
-
-        let keypair_bytes =
-            base64::decode(ctx.secret("SERVER_KEYPAIR")?.to_string()).map_err(|err| format!("{}",err))?;
-
-        let key_pair =
-            KeyPair::<curve25519_dalek::ristretto::RistrettoPoint>::from_private_key_slice(
-                keypair_bytes.as_slice(),
-            )
-            .map_err(|err| format!("{}",err))?;
-
-        let dummy_password_file = crate::opaque::register::generate_dummy_password_file(&key_pair, &values.username)?;
-        
-        let profile = data.get_profile(&values.username).await?;
-        console_log!("profile: {:?}", profile);
-        
-        let password_file;
-        let password_file_metadata;
-
-        if let Some((profile, metadata)) = profile {
-            password_file = base64::decode(profile.password_file).map_err(|err| format!("{}",err))?;
-            password_file_metadata = metadata;
-        } else {
-            password_file_metadata = UserProfileMetadata { v: 0, l: true, e: false };
-            password_file = dummy_password_file;
+            return ApiError::TooManyAttempts.response();
         }
 
-        let (state, response) = crate::opaque::login::start(
-            key_pair.private(),
-            &base64::decode(values.request).map_err(|err| format!("{}",err))?,
-            &password_file,
+        let key_ring = KeyRing::new(&ctx)?;
+        let current_key_id = key_ring.current_id();
+        let current_suite = opaque::cipher_suite(&ctx)?;
+
+        // Users that didn't confirm their email yet can log in as far as being told so
+        let profile = data.get_profile_or_pending(&values.username).await?;
+
+        // Unknown usernames get a fake credential response derived from the server key and the
+        // username, with a suite and profile picked like the ones of real accounts
+        let (password_file, password_file_metadata) = match profile {
+            Some((profile, metadata)) => (Some(base64::decode(profile.password_file).map_err(|err| format!("{}",err))?), metadata),
+            None => {
+                let current_key = key_ring.get(current_key_id).ok_or("Missing current server key")?;
+                let (s, h) = opaque::fake_suite_and_profile(
+                    current_key.private(),
+                    &values.username,
+                    current_suite,
+                    opaque::hash_profile(&ctx)?,
+                    opaque::fake_outdated_percent(&ctx),
+                )?;
+                (None, UserProfileMetadata { v: 0, l: true, e: false, k: current_key_id, h, s })
+            },
+        };
+
+        let key_pair = match key_ring.get(password_file_metadata.k) {
+            Some(key_pair) => key_pair,
+            None => return ApiError::ServerKeyUnavailable.response(),
+        };
+
+        let request = match client_input!(opaque::offered_request(&values.request, password_file_metadata.s)) {
+            Some(request) => request,
+            None => return ApiError::UnsupportedCipherSuite.response(),
+        };
+
+        let (state, response) = client_input!(crate::opaque::login::start(
+            password_file_metadata.s,
+            key_pair,
+            &request,
+            password_file.as_deref(),
             &values.username,
-            password_file_metadata.v,
-        )?;
+        ));
 
-        data.set_login_state(&values.username, state).await?;
+        if !data.start_login(&values.username, state).await? {
+            return ApiError::TooManyAttempts.response();
+        }
 
-        return worker::Response::ok(base64::encode(&response));
+        return worker::Response::ok(base64::encode(cipher_suite::tag_message(password_file_metadata.s, password_file_metadata.h, &response)));
     }
-    worker::Response::error("Bad Request", 400)
+    ApiError::BadRequest.response()
 }
 
-pub async fn finish_handler<D>(mut req: worker::Request, ctx: worker::RouteContext<D>) -> worker::Result<worker::Response>
+async fn notify_new_device(req: &worker::Request, ctx: &worker::RouteContext<worker::Env>, username: &str, mail: &str) -> worker::Result<()> {
+    let sender = crate::email::sender(ctx)?;
+    let device = req.headers().get("User-Agent")?.unwrap_or_default();
+    crate::confirmation_email::send_new_device_login(&Mailer::new(sender.as_ref(), ctx, req)?, username, mail, &device).await
+}
+
+pub async fn finish_handler(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
 {
-    if let Ok(values) = req.json::<LoginRequest>().await {
+    if let Ok(values) = req.json::<LoginFinishRequest>().await {
         if !crate::utils::validate_username(&values.username) {
-            return worker::Response::error("Invalid username", 400);
+            return ApiError::InvalidUsername.response();
         }
         
-        let data = AuthenticationData::new(&ctx);
+        let data = data::store(&ctx)?;
 
-        let state = data.get_login_state(&values.username).await?;
+        let state = match data.get_login_state(&values.username).await? {
+            Some(state) => state,
+            None => return ApiError::NoLoginState.response(),
+        };
 
-        if state.is_none() {
-            return worker::Response::error("No login state", 400);
+        let profile = data.get_profile_or_pending(&values.username).await?;
+        if profile.is_none() && !super::uniform_responses(&ctx) {
+            return ApiError::UnknownUser.response();
         }
 
+        let finalization = client_input!(base64::decode(values.request));
+        let session_key = crate::opaque::login::finish(&state, &finalization, &values.username);
 
+        data.remove_login_state(&values.username).await?;
 
-        let profile = data.get_profile(&values.username).await?;
-        if profile.is_none() {
-            return worker::Response::error("Username does not exist", 400);
+        // A finalization that doesn't verify means the client didn't know the password
+        let session_key = match session_key {
+            Ok(session_key) => session_key,
+            Err(_) => return ApiError::InvalidKeyConfirmation.response(),
+        };
+
+        let client_confirmation = client_input!(base64::decode(values.confirmation));
+        if !verify_confirmation_tag(&session_key, CLIENT_CONFIRMATION_LABEL, &values.username, &client_confirmation) {
+            return ApiError::InvalidKeyConfirmation.response();
         }
 
-        let (_, metadata) = unwrap_abort(profile);
+        // The account state is only told to a client that proved it knows the password
+        let (profile, metadata) = match profile {
+            Some(profile) => profile,
+            None => return ApiError::InvalidKeyConfirmation.response(),
+        };
         if !metadata.e {
-            return worker::Response::error("Email not verified", 403);
+            return ApiError::EmailNotVerified.response();
         }
         if metadata.l {
-            return worker::Response::error("Account locked", 401);
+            return ApiError::AccountLocked.response();
         }
 
-        let session_key = crate::opaque::login::finish( 
-                    &unwrap_abort(state), 
-                    &base64::decode(values.request).map_err(|err| format!("{}",err))?);
-
-        data.remove_login_state(&values.username).await?;
-
-        let session_key = session_key?;
+        let config = SessionConfig::new(&ctx);
+        let session = SessionToken::generate();
+        let now = crate::utils::now_millis();
+        data.set_login_session(&LoginSession { id: session.id(), username: values.username.clone(), created: now, last_used: now }, config.idle_ttl.min(config.absolute_ttl)).await?;
+        data.record_audit_event(&values.username, "login").await?;
+
+        let key_ring = KeyRing::new(&ctx)?;
+        let device_key = device::cookie_key(&ctx)?;
+        if let Some(device_key) = &device_key {
+            if !device::known_device(crate::utils::cookie(&req, device::DEVICE_COOKIE)?.as_deref(), device_key, &values.username) {
+                // the login goes on if the notice can't be sent
+                if let Err(err) = notify_new_device(&req, &ctx, &values.username, &profile.mail).await {
+                    console_log!("New device notice failed: {}", err);
+                }
+            }
+        }
 
-        data.set_login_session(&values.username, &session_key).await?;
-        return worker::Response::ok(base64::encode(&session_key));
+        let mut response = worker::Response::from_json(&LoginFinishResponse {
+            confirmation: base64::encode(confirmation_tag(&session_key, SERVER_CONFIRMATION_LABEL, &values.username)),
+            upgrade: opaque::outdated(&metadata, key_ring.current_id(), opaque::cipher_suite(&ctx)?, opaque::hash_profile(&ctx)?),
+        })?;
+        response.headers_mut().set("Set-Cookie", &session.set_cookie_header(&config))?;
+        if let Some(device_key) = &device_key {
+            response.headers_mut().append("Set-Cookie", &device::set_cookie_header(device_key, &values.username)?)?;
+        }
+        return Ok(response);
     }
-    worker::Response::error("Bad Request", 400)
+    ApiError::BadRequest.response()
 }
\ No newline at end of file
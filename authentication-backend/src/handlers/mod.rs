@@ -1,2 +1,12 @@
 pub mod register;
-pub mod login;
\ No newline at end of file
+pub mod login;
+pub mod session;
+pub mod password;
+pub mod account;
+pub mod admin;
+
+// With the `UNIFORM_RESPONSES` var set to "true", registration and login answer the same whether
+// the username exists or not, at the cost of vaguer errors for users who mistype it
+pub fn uniform_responses<D>(ctx: &worker::RouteContext<D>) -> bool {
+    ctx.var("UNIFORM_RESPONSES").is_ok_and(|value| value.to_string() == "true")
+}
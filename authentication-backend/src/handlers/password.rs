@@ -0,0 +1,269 @@
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use worker::console_log;
+
+use authentication_api::{PasswordChangeRequest, PasswordResetRegistrationRequest, PasswordResetRequest};
+use authentication_rs_lib::cipher_suite;
+
+use crate::{data::{self, AuthenticationStore, profile::UserProfileMetadata}, email::Mailer, error::{client_input, ApiError}, keys::KeyRing, opaque, session::{self, SessionConfig}};
+
+// Least time a reset request takes to answer, in millis, longer than sending an email
+const RESET_REQUEST_DURATION: u64 = 3_000;
+
+async fn valid_reset_token(data: &dyn AuthenticationStore, username: &str, token: &str) -> worker::Result<bool> {
+    let token = match base64::decode_config(token, base64::URL_SAFE) {
+        Ok(token) => token,
+        Err(_) => return Ok(false),
+    };
+    if let Some(token_hash) = data.get_password_reset_token(username).await? {
+        return Ok(bool::from(Sha256::digest(&token).as_slice().ct_eq(&token_hash)));
+    }
+    Ok(false)
+}
+
+async fn send_password_reset(req: &worker::Request, ctx: &worker::RouteContext<worker::Env>, username: &str, mail: &str, token: &str) -> worker::Result<()> {
+    let sender = crate::email::sender(ctx)?;
+    crate::confirmation_email::send_password_reset(&Mailer::new(sender.as_ref(), ctx, req)?, username, mail, token).await
+}
+
+async fn notify_password_changed(req: &worker::Request, ctx: &worker::RouteContext<worker::Env>, username: &str, mail: &str) -> worker::Result<()> {
+    let sender = crate::email::sender(ctx)?;
+    crate::confirmation_email::send_password_changed(&Mailer::new(sender.as_ref(), ctx, req)?, username, mail).await
+}
+
+// A new password file for the logged in user, either with a new password or, for an upgrade,
+// the same password with the newest server key, cipher suite and Argon2 profile
+async fn authenticated_registration_start(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>, key_upgrade: bool) -> worker::Result<worker::Response>
+{
+    let data = data::store(&ctx)?;
+    let config = SessionConfig::new(&ctx);
+
+    let authenticated = match session::authenticate(&req, data.as_ref(), &config).await? {
+        Some(authenticated) => authenticated,
+        None => return ApiError::Unauthorized.response(),
+    };
+
+    if let Ok(values) = req.json::<PasswordChangeRequest>().await {
+        let username = &authenticated.profile.username;
+
+        let key_ring = KeyRing::new(&ctx)?;
+        let (key_id, key_pair) = key_ring.current()?;
+        let suite = opaque::cipher_suite(&ctx)?;
+        let hash_profile = opaque::hash_profile(&ctx)?;
+
+        if key_upgrade && !opaque::outdated(&authenticated.metadata, key_id, suite, hash_profile) {
+            return ApiError::PasswordFileUpToDate.response();
+        }
+
+        let request = match client_input!(opaque::offered_request(&values.request, suite)) {
+            Some(request) => request,
+            None => return ApiError::UnsupportedCipherSuite.response(),
+        };
+        let (state, response) = client_input!(crate::opaque::register::start(suite, key_pair, &request, username));
+
+        let state = opaque::register::tag_state(key_id, suite, hash_profile, state);
+        let started = if key_upgrade { data.start_password_upgrade(username, state) } else { data.start_password_change(username, state) }.await?;
+        if !started {
+            return ApiError::TooManyAttempts.response();
+        }
+
+        return worker::Response::ok(base64::encode(cipher_suite::tag_message(suite, hash_profile, &response)));
+    }
+    ApiError::BadRequest.response()
+}
+
+async fn authenticated_registration_finish(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>, key_upgrade: bool) -> worker::Result<worker::Response>
+{
+    let data = data::store(&ctx)?;
+    let config = SessionConfig::new(&ctx);
+
+    let authenticated = match session::authenticate(&req, data.as_ref(), &config).await? {
+        Some(authenticated) => authenticated,
+        None => return ApiError::Unauthorized.response(),
+    };
+
+    if let Ok(values) = req.json::<PasswordChangeRequest>().await {
+        let username = authenticated.profile.username.clone();
+
+        let state = if key_upgrade { data.get_password_upgrade_state(&username) } else { data.get_password_change_state(&username) }.await?;
+        let state = match state {
+            Some(state) => state,
+            None => return ApiError::NoRegistrationState.response(),
+        };
+        let registration = match opaque::register::untag_state(&state) {
+            Some(registration) => registration,
+            None => return ApiError::NoRegistrationState.response(),
+        };
+
+        let upload = client_input!(base64::decode(values.request));
+
+        let password_file = crate::opaque::register::finish(registration.suite, registration.state, &upload);
+
+        if key_upgrade { data.remove_password_upgrade_state(&username) } else { data.remove_password_change_state(&username) }.await?;
+
+        let password_file = client_input!(password_file);
+
+        // The whole profile is a single KV value, so the password file is swapped in one write
+        let mut profile = authenticated.profile;
+        profile.password_file = base64::encode(password_file);
+        let metadata = authenticated.metadata;
+
+        // The server can't tell the password of an upgrade is the same one, so it's taken as a
+        // change like any other
+        data.save_profile(&username, &profile, &UserProfileMetadata { v: metadata.v.wrapping_add(1), k: registration.key_id, h: registration.hash_profile, s: registration.suite, ..metadata }).await?;
+
+        // Every other device has to log in again with the new password
+        for session in data.list_login_sessions(&username).await? {
+            if session.id != authenticated.session.id {
+                data.remove_login_session(&session.id, &username).await?;
+            }
+        }
+        data.record_audit_event(&username, if key_upgrade { "key_upgraded" } else { "password_changed" }).await?;
+
+        // the password is already saved if the notice can't be sent
+        if let Err(err) = notify_password_changed(&req, &ctx, &username, &profile.mail).await {
+            console_log!("Password change notice failed: {}", err);
+        }
+
+        return worker::Response::ok("");
+    }
+    ApiError::BadRequest.response()
+}
+
+pub async fn change_start_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    authenticated_registration_start(req, ctx, false).await
+}
+
+pub async fn change_finish_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    authenticated_registration_finish(req, ctx, false).await
+}
+
+pub async fn upgrade_start_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    authenticated_registration_start(req, ctx, true).await
+}
+
+pub async fn upgrade_finish_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    authenticated_registration_finish(req, ctx, true).await
+}
+
+pub async fn reset_request_handler(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    if let Ok(values) = req.json::<PasswordResetRequest>().await {
+        if !crate::utils::validate_username(&values.username) {
+            return ApiError::InvalidUsername.response();
+        }
+
+        let data = data::store(&ctx)?;
+        let started = crate::utils::now_millis();
+
+        // The response is the same whether the account exists or not, so it can't be used to find
+        // usernames. Sending the email takes time, so every answer waits as long as a send could.
+        if !data.too_many_password_reset_requests(&values.username).await? {
+            if let Some((profile, metadata)) = data.get_profile(&values.username).await? {
+                if metadata.e && !metadata.l {
+                    let token = crate::confirmation_email::generate_key();
+                    let token_bytes = base64::decode_config(&token, base64::URL_SAFE).map_err(|err| format!("{}",err))?;
+                    data.set_password_reset_token(&values.username, &Sha256::digest(&token_bytes)).await?;
+
+                    if let Err(err) = send_password_reset(&req, &ctx, &values.username, &profile.mail, &token).await {
+                        // a link that was never received can't be used, and a new one can be asked for
+                        data.remove_password_reset_token(&values.username).await?;
+                        console_log!("Password reset email failed: {}", err);
+                    }
+                }
+            }
+        }
+
+        crate::utils::sleep_until(started + RESET_REQUEST_DURATION).await?;
+        return worker::Response::ok("");
+    }
+    ApiError::BadRequest.response()
+}
+
+pub async fn reset_start_handler(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    if let Ok(values) = req.json::<PasswordResetRegistrationRequest>().await {
+        if !crate::utils::validate_username(&values.username) {
+            return ApiError::InvalidUsername.response();
+        }
+
+        let data = data::store(&ctx)?;
+
+        if !valid_reset_token(data.as_ref(), &values.username, &values.token).await? {
+            return ApiError::InvalidResetToken.response();
+        }
+
+        let key_ring = KeyRing::new(&ctx)?;
+        let (key_id, key_pair) = key_ring.current()?;
+        let suite = opaque::cipher_suite(&ctx)?;
+        let hash_profile = opaque::hash_profile(&ctx)?;
+
+        let request = match client_input!(opaque::offered_request(&values.request, suite)) {
+            Some(request) => request,
+            None => return ApiError::UnsupportedCipherSuite.response(),
+        };
+        let (state, response) = client_input!(crate::opaque::register::start(suite, key_pair, &request, &values.username));
+
+        if !data.start_password_reset(&values.username, opaque::register::tag_state(key_id, suite, hash_profile, state)).await? {
+            return ApiError::TooManyAttempts.response();
+        }
+
+        return worker::Response::ok(base64::encode(cipher_suite::tag_message(suite, hash_profile, &response)));
+    }
+    ApiError::BadRequest.response()
+}
+
+pub async fn reset_finish_handler(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    if let Ok(values) = req.json::<PasswordResetRegistrationRequest>().await {
+        if !crate::utils::validate_username(&values.username) {
+            return ApiError::InvalidUsername.response();
+        }
+
+        let data = data::store(&ctx)?;
+
+        if !valid_reset_token(data.as_ref(), &values.username, &values.token).await? {
+            return ApiError::InvalidResetToken.response();
+        }
+
+        let (mut profile, metadata) = match data.get_profile(&values.username).await? {
+            Some(profile) => profile,
+            None => return ApiError::InvalidResetToken.response(),
+        };
+
+        let state = match data.get_password_reset_state(&values.username).await? {
+            Some(state) => state,
+            None => return ApiError::NoRegistrationState.response(),
+        };
+        let registration = match opaque::register::untag_state(&state) {
+            Some(registration) => registration,
+            None => return ApiError::NoRegistrationState.response(),
+        };
+
+        let upload = client_input!(base64::decode(values.request));
+
+        let password_file = crate::opaque::register::finish(registration.suite, registration.state, &upload);
+
+        data.remove_password_reset_state(&values.username).await?;
+
+        let password_file = client_input!(password_file);
+
+        // Tokens are single use
+        data.remove_password_reset_token(&values.username).await?;
+
+        profile.password_file = base64::encode(password_file);
+        data.save_profile(&values.username, &profile, &UserProfileMetadata { v: metadata.v.wrapping_add(1), k: registration.key_id, h: registration.hash_profile, s: registration.suite, ..metadata }).await?;
+
+        for session in data.list_login_sessions(&values.username).await? {
+            data.remove_login_session(&session.id, &values.username).await?;
+        }
+        data.record_audit_event(&values.username, "password_reset").await?;
+
+        return worker::Response::ok("");
+    }
+    ApiError::BadRequest.response()
+}
@@ -1,25 +1,19 @@
 use std::collections::HashMap;
 
-use opaque_ke::keypair::KeyPair;
-use regex::Regex;
-use serde::{Deserialize, Serialize};
+use authentication_api::{EmailConfirmationResendRequest, RegistrationRequest};
+use authentication_rs_lib::cipher_suite;
 
-use crate::{data::{AuthenticationData, profile::{ProfileData, UserProfile}, register::RegistrationData}, utils::{unwrap_abort, unwrap_res_abort}};
+use crate::{data::{self, AuthenticationStore, profile::{UserProfile, UserProfileMetadata}}, email::Mailer, error::{client_input, ApiError}, keys::KeyRing, opaque};
 
-#[derive(Deserialize)]
-struct HttpRegistrationRequest {
-    username: String,
-    mail: String,
-    request: String,
-}
+// A new confirmation email can only be asked for this long after the last one, in seconds
+const CONFIRMATION_RESEND_THROTTLE: u64 = 5 * 60;
 
-#[derive(Serialize)]
-struct ValidationErrors {
-    message: String,
-    fields: HashMap<String, String>,
+// How long an email verification key is valid, in seconds
+fn email_verification_ttl(ctx: &worker::RouteContext<worker::Env>) -> u64 {
+    ctx.var("EMAIL_VERIFICATION_TTL").ok().and_then(|value| value.to_string().parse().ok()).unwrap_or(24 * 60 * 60)
 }
 
-fn validate_request(req: &HttpRegistrationRequest) -> Option<worker::Result<worker::Response>> {
+fn validate_request(req: &RegistrationRequest) -> Option<worker::Result<worker::Response>> {
     
     let mut request_valid = true;
     let mut errors_hashmap: HashMap<String, String> = HashMap::new();
@@ -29,14 +23,13 @@ fn validate_request(req: &HttpRegistrationRequest) -> Option<worker::Result<work
         errors_hashmap.insert("username".to_string(), "Invalid username, please only use letters, numbers, underscores, dashes, and periods. Usernames must be between 3 and 15 characters long.".to_string());
     }
 
-    let email_re = unwrap_res_abort(Regex::new(r#"^[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]+@[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(?:\.[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*$"#));
-
-    if req.mail.len() < 5 || req.mail.len() > 100 || !email_re.is_match(&req.mail) {
+    if !crate::utils::validate_mail(&req.mail) {
         request_valid = false;
         errors_hashmap.insert("mail".to_string(), "Invalid email address, please enter a valid email address.".to_string());
     }
 
-    if req.request.len() < 5 || req.request.len() > 256  {
+    // start requests hold a message for every cipher suite the client was built with
+    if req.request.len() < 5 || req.request.len() > 1024  {
         request_valid = false;
         errors_hashmap.insert("request".to_string(), "Invalid request payload.".to_string());
     }
@@ -44,133 +37,241 @@ fn validate_request(req: &HttpRegistrationRequest) -> Option<worker::Result<work
     if request_valid {
         return None;
     }
-    
-    let validation_errors = ValidationErrors {
-        message: "Some field(s) have invalid data".to_string(),
-        fields: errors_hashmap
-    };
 
-    
-    Some(worker::Response::error(serde_json::to_string(&validation_errors).unwrap_or(validation_errors.message), 400))
+    Some(ApiError::InvalidFields(errors_hashmap).response())
+}
+
+// Why the username and email can't be registered, `None` if both are free
+async fn registration_taken(data: &dyn AuthenticationStore, username: &str, mail: &str) -> worker::Result<Option<ApiError>> {
+    if data.profile_already_registered(username).await? {
+        return Ok(Some(ApiError::UserAlreadyRegistered));
+    }
+    if data.profile_already_registered_waiting_mail_confirm(username).await? {
+        return Ok(Some(ApiError::RegistrationPendingEmailConfirmation));
+    }
+    if data.mail_owner(mail).await?.is_some() {
+        return Ok(Some(ApiError::EmailAlreadyInUse));
+    }
+    Ok(None)
 }
 
-pub async fn start_handler<D>(mut req: worker::Request, ctx: worker::RouteContext<D>) -> worker::Result<worker::Response>
+pub async fn start_handler(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
 {
-    match req.json::<HttpRegistrationRequest>().await {
+    match req.json::<RegistrationRequest>().await {
         Ok(values) => {
             if let Some(err) = validate_request(&values) {
                 return err;
             }
 
-            let keypair_bytes =
-                unwrap_res_abort(base64::decode(ctx.secret("SERVER_KEYPAIR")?.to_string()));
-
-            let data = AuthenticationData::new(&ctx);
+            let key_ring = KeyRing::new(&ctx)?;
 
-            if data.profile_already_registered(&values.username).await? {
-                return worker::Response::error("User already registered", 400);
-            }
+            let data = data::store(&ctx)?;
 
-            if data.profile_already_registered_waiting_mail_confirm(&values.username).await? {
-                return worker::Response::error("User already registered, missing confirming email", 400);
+            // Uniform responses carry on with a taken username, `finish_handler` then drops it
+            if let Some(err) = registration_taken(data.as_ref(), &values.username, &values.mail).await?.filter(|_| !super::uniform_responses(&ctx)) {
+                return err.response();
             }
 
             if data.too_many_registration_attempts(&values.username).await? {
-                return worker::Response::error("Too many registration retries", 400);
+                return ApiError::TooManyAttempts.response();
             }
             
-            let key_pair =
-                unwrap_res_abort(
-                    KeyPair::<curve25519_dalek::ristretto::RistrettoPoint>::from_private_key_slice(
-                    keypair_bytes.as_slice(),
-                ));
+            let (key_id, key_pair) = key_ring.current()?;
+            let suite = opaque::cipher_suite(&ctx)?;
+            let hash_profile = opaque::hash_profile(&ctx)?;
 
-            let (state, response) = crate::opaque::register::start(key_pair.public(), &base64::decode(values.request).map_err(|err| format!("{}",err))?)?;
+            let request = match client_input!(opaque::offered_request(&values.request, suite)) {
+                Some(request) => request,
+                None => return ApiError::UnsupportedCipherSuite.response(),
+            };
+            let (state, response) = client_input!(crate::opaque::register::start(suite, key_pair, &request, &values.username));
 
 
-            data.set_registration_state(&values.username, state).await?;
+            if !data.start_registration(&values.username, opaque::register::tag_state(key_id, suite, hash_profile, state)).await? {
+                return ApiError::TooManyAttempts.response();
+            }
 
-            worker::Response::ok(base64::encode(&response))
+            worker::Response::ok(base64::encode(cipher_suite::tag_message(suite, hash_profile, &response)))
         }
-        Err(ref e) =>
-            worker::Response::error(format!("{}", e), 400)
+        Err(_) =>
+            ApiError::BadRequest.response()
     }
 }
 
-pub async fn finish_handler<D>(mut req: worker::Request, ctx: worker::RouteContext<D>) -> worker::Result<worker::Response>
+pub async fn finish_handler(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
 {
-    match req.json::<HttpRegistrationRequest>().await {
+    match req.json::<RegistrationRequest>().await {
         Ok(values) => {
 
             if let Some(err) = validate_request(&values) {
                 return err;
             }
 
-            let data = AuthenticationData::new(&ctx);
-
-            if data.profile_already_registered(&values.username).await? {
-                return worker::Response::error("User already registered", 400);
-            }
+            let data = data::store(&ctx)?;
 
-            if data.profile_already_registered_waiting_mail_confirm(&values.username).await? {
-                return worker::Response::error("User already registered, missing confirming email", 400);
+            let uniform = super::uniform_responses(&ctx);
+            let taken = registration_taken(data.as_ref(), &values.username, &values.mail).await?;
+            if let Some(err) = taken.clone().filter(|_| !uniform) {
+                return err.response();
             }
 
-            let state = data.get_registration_state(&values.username).await?;
+            let state = match data.get_registration_state(&values.username).await? {
+                Some(state) => state,
+                None => return ApiError::NoRegistrationState.response(),
+            };
+            let registration = match opaque::register::untag_state(&state) {
+                Some(registration) => registration,
+                None => return ApiError::NoRegistrationState.response(),
+            };
 
-            if state.is_none() {
-                return worker::Response::error("No registration state", 400);
-            }
-            let state = unwrap_abort(state);
+            let upload = client_input!(base64::decode(values.request));
 
-            let password_file =
-                crate::opaque::register::finish(&state, &base64::decode(values.request).map_err(|err| format!("{}",err))?);
+            let password_file = crate::opaque::register::finish(registration.suite, registration.state, &upload);
             
             data.remove_registration_state(&values.username).await?;
 
-            let password_file = password_file?;
+            let password_file = client_input!(password_file);
 
-            let email_verification_key = crate::confirmation_email::send(&values.username, &values.mail, ctx.secret("EMAILER_KEY")?.to_string()).await?;
+            if taken.is_some() {
+                return worker::Response::ok("");
+            }
 
-            let profile = UserProfile {
+            let ttl = email_verification_ttl(&ctx);
+            let email_verification_key = crate::confirmation_email::generate_key();
+            let mut profile = UserProfile {
                 username: values.username.to_string(),
-                mail: values.mail,
+                mail: crate::utils::normalize_mail(&values.mail),
                 password_file: base64::encode(password_file),
-                email_verification: email_verification_key,
+                email_verification: String::new(),
+                email_verification_issued: 0,
             };
+            profile.set_email_verification(&email_verification_key, crate::utils::now_millis());
             
-            data.save_profile(&values.username, &profile, 0, false, false).await?;
+            // The account only becomes a registered profile once its email is confirmed. It is
+            // stored before the email is sent, so no key goes out for a username that was taken.
+            if !data.create_pending_profile(&values.username, &profile, &UserProfileMetadata { v: 0, l: false, e: false, k: registration.key_id, h: registration.hash_profile, s: registration.suite }, ttl).await? {
+                return if uniform { worker::Response::ok("") } else { ApiError::UserAlreadyRegistered.response() };
+            }
+
+            let sender = crate::email::sender(&ctx)?;
+            let mailer = Mailer::new(sender.as_ref(), &ctx, &req)?;
+            if let Err(err) = crate::confirmation_email::send(&mailer, &values.username, &profile.mail, &email_verification_key, ttl).await {
+                // the username is free again for a new attempt
+                data.remove_pending_profile(&values.username).await?;
+                return Err(err);
+            }
+            data.record_audit_event(&values.username, "registered").await?;
 
             worker::Response::ok("")
         }
-        Err(e) =>
-            worker::Response::error(format!("{}", e), 400)
+        Err(_) =>
+            ApiError::BadRequest.response()
     }
 }
 
-pub async fn confirm_mail_handler<D>(req: worker::Request, ctx: worker::RouteContext<D>) -> worker::Result<worker::Response>
+pub async fn confirm_mail_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
 {
     if let Some(username) = ctx.param("username") {
-        let email_key = unwrap_res_abort(req.url()).query_pairs().find(|(key, _)| key == "k").map_or(String::new(), |(_, value)| value.to_string());
+        let email_key = req.url()?.query_pairs().find(|(key, _)| key == "k").map_or(String::new(), |(_, value)| value.to_string());
         let email_key_result = base64::decode_config(&email_key, base64::URL_SAFE);
         if crate::utils::validate_username(username) && email_key_result.is_ok() {
-            let data = AuthenticationData::new(&ctx);
-            let pending_user_profile = data.get_profile(username).await?;
-            if let Some((profile, meta)) = pending_user_profile {
-                if meta.e {
-                    return worker::Response::error("Email already verified", 200);
-                }
-                if meta.l {
-                    return worker::Response::error("Account locked", 401);
+            let data = data::store(&ctx)?;
+            let ttl = email_verification_ttl(&ctx);
+            let now = crate::utils::now_millis();
+            // Without the right key the answer is the same as for an unknown username
+            if let Some((mut profile, meta)) = data.get_pending_profile(username).await? {
+                if profile.email_verification_matches(&email_key, ttl, now) {
+                    // Another account may have registered or moved to the address since
+                    if data.mail_owner(&profile.mail).await?.is_some() {
+                        return ApiError::EmailAlreadyInUse.response();
+                    }
+                    profile.clear_email_verification();
+                    let promoted = match data.promote_pending_profile(username, &profile, &UserProfileMetadata { e: true, ..meta }).await {
+                        Ok(promoted) => promoted,
+                        // D1 refuses a taken address on its own, for one taken since the check
+                        Err(_) if data.mail_owner(&profile.mail).await?.is_some() => return ApiError::EmailAlreadyInUse.response(),
+                        Err(err) => return Err(err),
+                    };
+                    if !promoted {
+                        return ApiError::UserAlreadyRegistered.response();
+                    }
+                    data.record_audit_event(username, "email_confirmed").await?;
+                    return worker::Response::ok("");
                 }
-
-                if profile.email_verification == email_key {
-                    data.save_profile(username, &profile, 0, false, true).await?;
+            } else if let Some((mut profile, meta)) = data.get_profile(username).await? {
+                // Unverified profiles registered before there were pending ones
+                if profile.email_verification_matches(&email_key, ttl, now) {
+                    if meta.e {
+                        return ApiError::EmailAlreadyVerified.response();
+                    }
+                    if meta.l {
+                        return ApiError::AccountLocked.response();
+                    }
+                    if data.mail_owner(&profile.mail).await?.is_some_and(|owner| owner != username.as_str()) {
+                        return ApiError::EmailAlreadyInUse.response();
+                    }
+                    // The key can only be used once
+                    profile.clear_email_verification();
+                    data.save_profile(username, &profile, &UserProfileMetadata { e: true, ..meta }).await?;
+                    data.record_audit_event(username, "email_confirmed").await?;
                     return worker::Response::ok("");
                 }
-            } 
+            }
+            return ApiError::InvalidConfirmationKey.response();
         }
     }
     
-    worker::Response::error("Bad request", 400)
-}
\ No newline at end of file
+    ApiError::BadRequest.response()
+}
+
+// A pending profile lives as long as its newest key
+async fn save_unconfirmed_profile(data: &dyn AuthenticationStore, profile: &UserProfile, meta: &UserProfileMetadata, pending: bool, ttl: u64) -> worker::Result<()> {
+    if pending {
+        data.save_pending_profile(&profile.username, profile, meta, ttl).await
+    } else {
+        data.save_profile(&profile.username, profile, meta).await
+    }
+}
+
+// Sends a new confirmation key to a user whose email is not verified yet. The answer is the same
+// whether anything was sent or not.
+pub async fn resend_confirmation_handler(mut req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    if let Ok(values) = req.json::<EmailConfirmationResendRequest>().await {
+        if !crate::utils::validate_username(&values.username) {
+            return ApiError::InvalidUsername.response();
+        }
+
+        let data = data::store(&ctx)?;
+
+        // `true` for a pending profile, `false` for an unverified one registered before them
+        let unconfirmed = match data.get_pending_profile(&values.username).await? {
+            Some(profile) => Some((profile, true)),
+            None => data.get_profile(&values.username).await?.filter(|(_, meta)| !meta.e).map(|profile| (profile, false)),
+        };
+
+        if let Some(((mut profile, meta), pending)) = unconfirmed {
+            let now = crate::utils::now_millis();
+            let throttled = profile.email_verification_issued + CONFIRMATION_RESEND_THROTTLE * 1000 > now;
+            if !meta.l && !throttled {
+                let ttl = email_verification_ttl(&ctx);
+                let email_verification_key = crate::confirmation_email::generate_key();
+                let previous = profile.clone();
+                profile.set_email_verification(&email_verification_key, now);
+                save_unconfirmed_profile(data.as_ref(), &profile, &meta, pending, ttl).await?;
+
+                let sender = crate::email::sender(&ctx)?;
+                let mailer = Mailer::new(sender.as_ref(), &ctx, &req)?;
+                if let Err(err) = crate::confirmation_email::send(&mailer, &values.username, &profile.mail, &email_verification_key, ttl).await {
+                    // the key that was sent before stays the valid one
+                    save_unconfirmed_profile(data.as_ref(), &previous, &meta, pending, ttl).await?;
+                    return Err(err);
+                }
+                data.record_audit_event(&values.username, "confirmation_resent").await?;
+            }
+        }
+
+        return worker::Response::ok("");
+    }
+    ApiError::BadRequest.response()
+}
@@ -0,0 +1,72 @@
+use authentication_api::{SessionListItem, SessionResponse};
+
+use crate::{data, error::ApiError, session::{self, SessionConfig, SessionToken}};
+
+pub async fn whoami_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    let data = data::store(&ctx)?;
+    let config = SessionConfig::new(&ctx);
+
+    if let Some(authenticated) = session::authenticate(&req, data.as_ref(), &config).await? {
+        return worker::Response::from_json(&SessionResponse {
+            username: authenticated.profile.username,
+            mail: authenticated.profile.mail,
+        });
+    }
+    ApiError::Unauthorized.response()
+}
+
+pub async fn logout_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    let data = data::store(&ctx)?;
+    let config = SessionConfig::new(&ctx);
+
+    if let Some(authenticated) = session::authenticate(&req, data.as_ref(), &config).await? {
+        data.remove_login_session(&authenticated.session.id, &authenticated.session.username).await?;
+        data.record_audit_event(&authenticated.session.username, "logout").await?;
+        let mut response = worker::Response::ok("")?;
+        response.headers_mut().set("Set-Cookie", &SessionToken::clear_cookie_header())?;
+        return Ok(response);
+    }
+    ApiError::Unauthorized.response()
+}
+
+pub async fn list_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    let data = data::store(&ctx)?;
+    let config = SessionConfig::new(&ctx);
+
+    if let Some(authenticated) = session::authenticate(&req, data.as_ref(), &config).await? {
+        let sessions: Vec<SessionListItem> = data.list_login_sessions(&authenticated.session.username).await?
+            .into_iter()
+            .map(|session| SessionListItem {
+                current: session.id == authenticated.session.id,
+                id: session.id,
+                created: session.created,
+            })
+            .collect();
+        return worker::Response::from_json(&sessions);
+    }
+    ApiError::Unauthorized.response()
+}
+
+pub async fn revoke_handler(req: worker::Request, ctx: worker::RouteContext<worker::Env>) -> worker::Result<worker::Response>
+{
+    let data = data::store(&ctx)?;
+    let config = SessionConfig::new(&ctx);
+
+    if let Some(authenticated) = session::authenticate(&req, data.as_ref(), &config).await? {
+        if let Some(session_id) = ctx.param("id") {
+            // Only sessions belonging to the authenticated user can be revoked
+            if let Some(session) = data.get_login_session(session_id).await? {
+                if session.username == authenticated.session.username {
+                    data.remove_login_session(&session.id, &session.username).await?;
+                    data.record_audit_event(&session.username, "session_revoked").await?;
+                    return worker::Response::ok("");
+                }
+            }
+        }
+        return ApiError::SessionNotFound.response();
+    }
+    ApiError::Unauthorized.response()
+}
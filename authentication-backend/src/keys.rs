@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+
+use curve25519_dalek::ristretto::RistrettoPoint;
+use opaque_ke::keypair::KeyPair;
+
+// Key id given to the single key of the `SERVER_KEYPAIR` secret
+pub const LEGACY_KEY_ID: u8 = 0;
+
+// Server OPAQUE keypairs by id. Password files are bound to the key they were registered with,
+// so older keys stay in the ring for login while new registrations use the newest one.
+pub struct KeyRing {
+    keys: BTreeMap<u8, KeyPair<RistrettoPoint>>,
+    current_id: u8,
+}
+
+impl KeyRing {
+    // Reads the `SERVER_KEYRING` secret, a JSON object of key id to base64 keypair, and the
+    // `SERVER_KEYPAIR` secret as key 0
+    pub fn new<D>(ctx: &worker::RouteContext<D>) -> worker::Result<Self> {
+        let legacy = ctx.secret("SERVER_KEYPAIR").ok().map(|secret| secret.to_string());
+        let ring = ctx.secret("SERVER_KEYRING").ok().map(|secret| secret.to_string());
+        Self::parse(legacy.as_deref(), ring.as_deref())
+    }
+
+    pub fn parse(legacy: Option<&str>, ring: Option<&str>) -> worker::Result<Self> {
+        let mut encoded_keys = match ring {
+            Some(ring) => serde_json::from_str::<BTreeMap<u8, String>>(ring).map_err(|err| format!("Invalid server key ring: {}", err))?,
+            None => BTreeMap::new(),
+        };
+        if let Some(legacy) = legacy {
+            encoded_keys.entry(LEGACY_KEY_ID).or_insert_with(|| legacy.to_string());
+        }
+
+        let mut keys = BTreeMap::new();
+        for (key_id, encoded_key) in encoded_keys {
+            let keypair_bytes = base64::decode(encoded_key.trim()).map_err(|err| format!("Invalid server key {}: {}", key_id, err))?;
+            let key_pair = KeyPair::<RistrettoPoint>::from_private_key_slice(&keypair_bytes).map_err(|err| format!("Invalid server key {}: {}", key_id, err))?;
+            keys.insert(key_id, key_pair);
+        }
+
+        let current_id = match keys.keys().next_back() {
+            Some(key_id) => *key_id,
+            None => return Err("No server key configured".into()),
+        };
+        Ok(Self { keys, current_id })
+    }
+
+    // Newest key, used for every new password file
+    pub fn current(&self) -> worker::Result<(u8, &KeyPair<RistrettoPoint>)> {
+        let key_pair = self.get(self.current_id).ok_or("No server key configured")?;
+        Ok((self.current_id, key_pair))
+    }
+
+    pub fn current_id(&self) -> u8 {
+        self.current_id
+    }
+
+    pub fn get(&self, key_id: u8) -> Option<&KeyPair<RistrettoPoint>> {
+        self.keys.get(&key_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use authentication_rs_lib::AuthenticationOpaque;
+    use opaque_ke::{ciphersuite::CipherSuite, rand::rngs::OsRng};
+
+    use super::{KeyRing, LEGACY_KEY_ID};
+
+    fn encoded_key() -> String {
+        base64::encode(AuthenticationOpaque::generate_random_keypair(&mut OsRng).private().to_arr())
+    }
+
+    #[test]
+    fn newest_key_is_current() {
+        let legacy = encoded_key();
+        let ring = format!(r#"{{"1": "{}", "2": "{}"}}"#, encoded_key(), encoded_key());
+        let key_ring = KeyRing::parse(Some(&legacy), Some(&ring)).unwrap();
+
+        assert_eq!(key_ring.current_id(), 2);
+        assert!(key_ring.get(LEGACY_KEY_ID).is_some());
+        assert!(key_ring.get(3).is_none());
+        assert!(KeyRing::parse(None, None).is_err());
+    }
+}
@@ -1,8 +1,13 @@
 mod utils;
 mod confirmation_email;
-mod data;
+pub mod data;
+mod device;
+pub mod email;
+mod error;
 mod handlers;
+mod keys;
 mod opaque;
+mod session;
 
 fn log_request(req: &Request) {
     console_log!(
@@ -14,18 +19,33 @@ fn log_request(req: &Request) {
     );
 }
 
-use worker::{Date, Env, Request, Response, Result, Router, console_log, event, wasm_bindgen, wasm_bindgen_futures, worker_sys};
+fn set_cors_headers(headers: &mut Headers, allowed_origin: &str) -> Result<()> {
+    headers.set("Access-Control-Allow-Origin", allowed_origin)?;
+    headers.set("Access-Control-Allow-Methods", "GET, POST, DELETE, OPTIONS")?;
+    headers.set("Access-Control-Allow-Headers", &format!("Content-Type, Authorization, {}", API_VERSION_HEADER))?;
+    headers.set("Access-Control-Expose-Headers", API_VERSION_HEADER)?;
+    headers.set(API_VERSION_HEADER, &API_VERSION.to_string())?;
+    if allowed_origin != "*" {
+        headers.set("Access-Control-Allow-Credentials", "true")?;
+    }
+    Ok(())
+}
+
+use authentication_api::{API_VERSION, API_VERSION_HEADER};
+use error::ApiError;
+use worker::{wasm_bindgen::JsValue, Date, Headers, Env, Request, Response, Result, Router, console_log, event, wasm_bindgen, wasm_bindgen_futures, worker_sys};
 
 #[event(fetch)]
 pub async fn main(req: Request, env: Env) -> Result<Response> {
     log_request(&req);
 
+    // Session cookies are only sent cross origin with credentials, which can't be combined with a
+    // wildcard origin, so the frontend origin has to be explicitly allowed.
+    let allowed_origin = env.var("ALLOWED_ORIGIN").map_or_else(|_| "*".to_string(), |origin| origin.to_string());
+
     if req.method() == worker::Method::Options {
         if let Ok(mut response) = Response::ok("") {
-            let headers = response.headers_mut();
-            headers.set("Access-Control-Allow-Origin", "*")?;
-            headers.set("Access-Control-Allow-Methods", "GET, POST, OPTIONS")?;
-            headers.set("Access-Control-Allow-Headers", "Content-Type")?;
+            set_cors_headers(response.headers_mut(), &allowed_origin)?;
             return Ok(response)
         }
         return Response::ok("")
@@ -34,10 +54,20 @@ pub async fn main(req: Request, env: Env) -> Result<Response> {
     // Optionally, get more helpful error messages written to the console in the case of a panic.
     utils::set_panic_hook();
 
+    // Requests without a version are from clients made before the API was versioned
+    if let Some(version) = req.headers().get(API_VERSION_HEADER)? {
+        if version.trim().parse::<u16>().ok() != Some(API_VERSION) {
+            let mut response = ApiError::UnsupportedApiVersion.response()?;
+            set_cors_headers(response.headers_mut(), &allowed_origin)?;
+            return Ok(response);
+        }
+    }
+
     // Optionally, use the Router to handle matching endpoints, use ":name" placeholders, or "*name"
-    // catch-alls to match on specific patterns. Alternatively, use `Router::with_data(D)` to
-    // provide arbitrary data that will be accessible in each route via the `ctx.data()` method.
-    let router = Router::new();
+    // catch-alls to match on specific patterns. The `Env` is also given as the router data, so
+    // handlers can reach bindings the `RouteContext` has no accessor for, like D1 databases.
+    // `Env` doesn't implement `Clone`, its `JsValue` is cloned instead.
+    let router = Router::with_data(Env::from(JsValue::clone(&env)));
 
     // Add as many routes as your Worker needs! Each route will get a `Request` for handling HTTP
     // functionality and a `RouteContext` which you can use to  and get route parameters and
@@ -47,12 +77,39 @@ pub async fn main(req: Request, env: Env) -> Result<Response> {
         .post_async("/register/start", handlers::register::start_handler)
         .post_async("/register/end", handlers::register::finish_handler)
         .get_async("/register/confirm/:username", handlers::register::confirm_mail_handler)
+        .post_async("/register/confirm/resend", handlers::register::resend_confirmation_handler)
         .post_async("/login/start", handlers::login::start_handler)
         .post_async("/login/end", handlers::login::finish_handler)
+        .get_async("/session", handlers::session::whoami_handler)
+        .post_async("/logout", handlers::session::logout_handler)
+        .get_async("/sessions", handlers::session::list_handler)
+        .delete_async("/sessions/:id", handlers::session::revoke_handler)
+        .post_async("/password/change/start", handlers::password::change_start_handler)
+        .post_async("/password/change/end", handlers::password::change_finish_handler)
+        .post_async("/password/upgrade/start", handlers::password::upgrade_start_handler)
+        .post_async("/password/upgrade/end", handlers::password::upgrade_finish_handler)
+        .post_async("/password/reset/request", handlers::password::reset_request_handler)
+        .post_async("/password/reset/start", handlers::password::reset_start_handler)
+        .post_async("/password/reset/end", handlers::password::reset_finish_handler)
+        .post_async("/account/email", handlers::account::email_change_handler)
+        .get_async("/account/email/confirm/:username", handlers::account::email_confirm_handler)
+        .get_async("/account/email/revert/:username", handlers::account::email_revert_handler)
+        .post_async("/admin/migrations/sessions", handlers::admin::migrate_sessions_handler)
+        .post_async("/admin/migrations/mail-index", handlers::admin::migrate_mail_index_handler)
+        .post_async("/admin/migrations/schema", handlers::admin::migrate_schema_handler)
         .get("/worker-version", |_, ctx| {
             let version = ctx.var("WORKERS_RS_VERSION")?.to_string();
             Response::ok(version)
         })
         .run(req, env)
         .await
+        // the cause of a failed handler is logged, the client only gets an internal error
+        .or_else(|err| {
+            console_log!("Handler failed: {}", err);
+            ApiError::Internal.response()
+        })
+        .and_then(|mut response| {
+            set_cors_headers(response.headers_mut(), &allowed_origin)?;
+            Ok(response)
+        })
 }
@@ -1,23 +1,45 @@
-use authentication_rs_lib::AuthenticationOpaque;
-use opaque_ke::{keypair::Key, ServerRegistration, rand::rngs::OsRng, ServerLogin, CredentialRequest, ServerLoginStartParameters, CredentialFinalization};
+use authentication_rs_lib::rfc;
+use curve25519_dalek::ristretto::RistrettoPoint;
+use opaque_ke::{keypair::KeyPair, ServerRegistration, rand::rngs::OsRng, ServerLogin, CredentialRequest, ServerLoginStartParameters, CredentialFinalization};
 
-use crate::utils::unwrap_res_abort;
+use super::{with_server_suite, ServerSuite};
 
+// The login state starts with the cipher suite so it can be finished with the same one. Without a
+// password file RFC suites answer with a fake response, legacy suites are only used by existing
+// password files.
 pub fn start(
-    server_key_private: &Key,
+    suite: u8,
+    server_key_pair: &KeyPair<RistrettoPoint>,
+    credential_request_bytes: &[u8],
+    password_file_bytes: Option<&[u8]>,
+    username: &str,
+) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let (state, response) = match (rfc::rfc_suite(suite), password_file_bytes) {
+        (Some(rfc_suite), password_file_bytes) =>
+            rfc_suite.server_login_start(server_key_pair.private(), password_file_bytes, credential_request_bytes, username)?,
+        (None, Some(password_file_bytes)) =>
+            with_server_suite!(suite, start_with_suite(server_key_pair, credential_request_bytes, password_file_bytes, username))?,
+        (None, None) => return Err("Missing password file".to_string()),
+    };
+    let mut tagged_state = vec![suite];
+    tagged_state.extend(state);
+    Ok((tagged_state, response))
+}
+
+fn start_with_suite<CS: ServerSuite>(
+    server_key_pair: &KeyPair<RistrettoPoint>,
     credential_request_bytes: &[u8],
     password_file_bytes: &[u8],
     username: &str,
-    _version: u8,
 ) -> Result<(Vec<u8>, Vec<u8>), String> {
     let password_file =
-    unwrap_res_abort(ServerRegistration::<AuthenticationOpaque>::deserialize(password_file_bytes));
+        ServerRegistration::<CS>::deserialize(password_file_bytes).map_err(|err| format!("{}",err))?;
     let mut server_rng = OsRng;
     let server_login_start_result = ServerLogin::start(
         &mut server_rng,
         password_file,
-        server_key_private,
-        unwrap_res_abort(CredentialRequest::<AuthenticationOpaque>::deserialize(credential_request_bytes)),
+        CS::key_pair(server_key_pair)?.private(),
+        CredentialRequest::<CS>::deserialize(credential_request_bytes).map_err(|err| format!("{}",err))?,
         ServerLoginStartParameters::WithIdentifiers(username.as_bytes().to_vec(), vec![]),
     )
     .map_err(|err| format!("{}",err))?;
@@ -28,12 +50,41 @@ pub fn start(
     ))
 }
 
-pub fn finish(server_login_state_bytes: &[u8], credential_finalization_bytes: &[u8]) -> Result<Vec<u8>, String> {
+pub fn finish(server_login_state_bytes: &[u8], credential_finalization_bytes: &[u8], username: &str) -> Result<Vec<u8>, String> {
+    match server_login_state_bytes {
+        [suite, state @ ..] => match rfc::rfc_suite(*suite) {
+            Some(rfc_suite) => rfc_suite.server_login_finish(state, credential_finalization_bytes, username),
+            None => with_server_suite!(*suite, finish_with_suite(state, credential_finalization_bytes)),
+        },
+        _ => Err("Empty login state".to_string()),
+    }
+}
+
+fn finish_with_suite<CS: ServerSuite>(server_login_state_bytes: &[u8], credential_finalization_bytes: &[u8]) -> Result<Vec<u8>, String> {
     let server_login_state =
-        ServerLogin::<AuthenticationOpaque>::deserialize(server_login_state_bytes).map_err(|err| format!("{}",err))?;
+        ServerLogin::<CS>::deserialize(server_login_state_bytes).map_err(|err| format!("{}",err))?;
     let server_login_finish_result = server_login_state
         .finish(CredentialFinalization::deserialize(credential_finalization_bytes).map_err(|err| format!("{}",err))?)
         .map_err(|err| format!("{}",err))?;
 
     Ok(server_login_finish_result.session_key)
 }
+
+#[cfg(test)]
+mod tests {
+    use authentication_rs_lib::{cipher_suite, AuthenticationOpaque};
+    use opaque_ke::{ciphersuite::CipherSuite, rand::rngs::OsRng};
+
+    use super::{finish, start};
+
+    #[test]
+    fn malformed_messages_are_errors() {
+        let key_pair = AuthenticationOpaque::generate_random_keypair(&mut OsRng);
+
+        assert!(start(cipher_suite::LEGACY_SUITE, &key_pair, &[1, 2, 3], Some(&[4, 5, 6]), "alice").is_err());
+        assert!(start(cipher_suite::LEGACY_SUITE, &key_pair, &[1, 2, 3], None, "alice").is_err());
+        assert!(start(cipher_suite::DEFAULT_SUITE, &key_pair, &[1, 2, 3], None, "alice").is_err());
+        assert!(start(cipher_suite::DEFAULT_SUITE, &key_pair, &[1, 2, 3], Some(&[4, 5, 6]), "alice").is_err());
+        assert!(finish(&[cipher_suite::LEGACY_SUITE, 1, 2, 3], &[4, 5, 6], "alice").is_err());
+    }
+}
@@ -1,2 +1,126 @@
+use authentication_rs_lib::{argon2_profile, cipher_suite, AuthenticationOpaque};
+use curve25519_dalek::ristretto::RistrettoPoint;
+use hmac::{Hmac, Mac, NewMac};
+use opaque_ke::{ciphersuite::CipherSuite, keypair::KeyPair};
+use sha2::Sha256;
+
+use crate::data::profile::UserProfileMetadata;
+
 pub mod register;
-pub mod login;
\ No newline at end of file
+pub mod login;
+
+// Server side types of a legacy (opaque-ke 1) cipher suite. They only depend on its group and
+// hash, so suites that differ by the client slow hash share them. RFC suites go through
+// `authentication_rs_lib::rfc::rfc_suite` instead.
+pub trait ServerSuite: CipherSuite {
+    // The key ring holds Ristretto255 keys
+    fn key_pair(key_pair: &KeyPair<RistrettoPoint>) -> Result<KeyPair<Self::Group>, String>;
+}
+
+impl ServerSuite for AuthenticationOpaque {
+    fn key_pair(key_pair: &KeyPair<RistrettoPoint>) -> Result<KeyPair<RistrettoPoint>, String> {
+        Ok(key_pair.clone())
+    }
+}
+
+// Calls `$function::<ServerSuite>` for a legacy cipher suite id
+macro_rules! with_server_suite {
+    ($suite:expr, $function:ident($($arg:expr),*)) => {
+        match $suite {
+            authentication_rs_lib::cipher_suite::RISTRETTO255_ARGON2 | authentication_rs_lib::cipher_suite::RISTRETTO255_PBKDF2 =>
+                $function::<authentication_rs_lib::AuthenticationOpaque>($($arg),*),
+            suite => Err(format!("Unsupported cipher suite {}", suite)),
+        }
+    };
+}
+pub(crate) use with_server_suite;
+
+// Argon2 profile for new password files, the `ARGON2_PROFILE` var holds its name or id
+pub fn hash_profile<D>(ctx: &worker::RouteContext<D>) -> worker::Result<u8> {
+    let name = match ctx.var("ARGON2_PROFILE") {
+        Ok(name) => name.to_string(),
+        Err(_) => return Ok(argon2_profile::DEFAULT_PROFILE),
+    };
+    let profile = argon2_profile::profile_by_name(&name)
+        .or_else(|| name.parse().ok().and_then(argon2_profile::profile))
+        .ok_or_else(|| format!("Unknown Argon2 profile {}", name))?;
+    Ok(profile.id)
+}
+
+// Cipher suite for new password files, the `CIPHER_SUITE` var holds its name or id. Suites behind
+// a cargo feature the worker was built without are rejected, and so are the legacy suites, which
+// can only be used by password files registered with them.
+pub fn cipher_suite<D>(ctx: &worker::RouteContext<D>) -> worker::Result<u8> {
+    let name = match ctx.var("CIPHER_SUITE") {
+        Ok(name) => name.to_string(),
+        Err(_) => return Ok(cipher_suite::DEFAULT_SUITE),
+    };
+    let suite = cipher_suite::suite_by_name(&name)
+        .or_else(|| name.parse().ok().and_then(cipher_suite::suite))
+        .ok_or_else(|| format!("Unknown cipher suite {}", name))?;
+    if !suite.rfc {
+        return Err(format!("Cipher suite {} is only kept for existing password files", suite.name).into());
+    }
+    Ok(suite.id)
+}
+
+// Share of unknown usernames, in percent, answered as if their password file predated the current
+// suite or Argon2 profile. It should follow the share of accounts that didn't log in since the
+// configuration changed, so the `FAKE_OUTDATED_PERCENT` var can be lowered as they're upgraded.
+pub fn fake_outdated_percent<D>(ctx: &worker::RouteContext<D>) -> u8 {
+    ctx.var("FAKE_OUTDATED_PERCENT").ok().and_then(|value| value.to_string().parse().ok()).unwrap_or(0).min(100)
+}
+
+// Suite and Argon2 profile told to an unknown username. Real accounts can still be on an earlier RFC
+// suite or profile, so some unknown usernames get one of those too. The pick is derived from the
+// server key and the username, an unknown username always gets the same answer like a real one.
+// Password files of the legacy suites can't be faked and stand out until they're upgraded.
+pub fn fake_suite_and_profile(server_private_key: &[u8], username: &str, suite: u8, hash_profile: u8, outdated_percent: u8) -> Result<(u8, u8), String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(server_private_key).map_err(|err| format!("{}",err))?;
+    mac.update(b"fake-suite-and-profile:");
+    mac.update(username.as_bytes());
+    let pick = mac.finalize().into_bytes();
+
+    let outdated: Vec<(u8, u8)> = cipher_suite::suites()
+        .filter(|suite| suite.rfc)
+        .flat_map(|suite| argon2_profile::PROFILES.iter().map(move |profile| (suite.id, profile.id)))
+        .filter(|candidate| *candidate != (suite, hash_profile))
+        .collect();
+    if outdated.is_empty() || u16::from_be_bytes([pick[0], pick[1]]) % 100 >= u16::from(outdated_percent) {
+        return Ok((suite, hash_profile));
+    }
+    Ok(outdated[usize::from(u16::from_be_bytes([pick[2], pick[3]])) % outdated.len()])
+}
+
+// The request the client made for a suite, `None` if it didn't offer it
+pub fn offered_request(request: &str, suite: u8) -> Result<Option<Vec<u8>>, String> {
+    let request = base64::decode(request).map_err(|err| format!("{}",err))?;
+    let offers = cipher_suite::decode_offers(&request).ok_or("Invalid request payload")?;
+    Ok(offers.into_iter().find(|(id, _)| *id == suite).map(|(_, message)| message.to_vec()))
+}
+
+// Whether the password file should be registered again with the current configuration
+pub fn outdated(metadata: &UserProfileMetadata, key_id: u8, suite: u8, hash_profile: u8) -> bool {
+    let argon2 = cipher_suite::suite(metadata.s).is_some_and(|suite| suite.argon2);
+    metadata.k != key_id || metadata.s != suite || (argon2 && metadata.h != hash_profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use authentication_rs_lib::{argon2_profile, cipher_suite};
+
+    use super::fake_suite_and_profile;
+
+    #[test]
+    fn fake_suite_and_profile_is_stable_per_username() {
+        let current = (cipher_suite::DEFAULT_SUITE, argon2_profile::DEFAULT_PROFILE);
+        let fake = |username: &str, percent: u8| fake_suite_and_profile(&[7; 32], username, current.0, current.1, percent).unwrap();
+
+        assert!((0..50).all(|index| fake(&format!("user{}", index), 0) == current));
+        assert!((0..50).all(|index| fake(&format!("user{}", index), 100) != current));
+        assert_eq!(fake("someone", 50), fake("someone", 50));
+
+        let outdated = (0..200).filter(|index| fake(&format!("user{}", index), 50) != current).count();
+        assert!((50..150).contains(&outdated));
+    }
+}
@@ -1,14 +1,24 @@
-use authentication_rs_lib::AuthenticationOpaque;
+use authentication_rs_lib::rfc;
 use curve25519_dalek::ristretto::RistrettoPoint;
-use opaque_ke::{keypair::{Key, KeyPair}, rand::rngs::OsRng, ServerRegistration, RegistrationRequest, RegistrationUpload, ClientRegistration, ClientRegistrationFinishParameters};
+use opaque_ke::{keypair::KeyPair, rand::rngs::OsRng, ServerRegistration, RegistrationRequest, RegistrationUpload};
 
-pub fn start(server_public_key: &Key, registration_request_bytes: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
+use super::{with_server_suite, ServerSuite};
+
+// RFC suites derive the OPRF key from the username and have no server state to keep
+pub fn start(suite: u8, server_key_pair: &KeyPair<RistrettoPoint>, registration_request_bytes: &[u8], username: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+    if let Some(rfc_suite) = rfc::rfc_suite(suite) {
+        return Ok((Vec::new(), rfc_suite.server_registration_start(server_key_pair.private(), registration_request_bytes, username)?));
+    }
+    with_server_suite!(suite, start_with_suite(server_key_pair, registration_request_bytes))
+}
+
+fn start_with_suite<CS: ServerSuite>(server_key_pair: &KeyPair<RistrettoPoint>, registration_request_bytes: &[u8]) -> Result<(Vec<u8>, Vec<u8>), String> {
     let mut server_rng = OsRng;
 
-    let server_registration_start_result = ServerRegistration::<AuthenticationOpaque>::start(
+    let server_registration_start_result = ServerRegistration::<CS>::start(
         &mut server_rng,
         RegistrationRequest::deserialize(registration_request_bytes).map_err(|err| format!("{}",err))?,
-        server_public_key,
+        CS::key_pair(server_key_pair)?.public(),
     )
     .map_err(|err| format!("{}",err))?;
 
@@ -18,36 +28,57 @@ pub fn start(server_public_key: &Key, registration_request_bytes: &[u8]) -> Resu
     ))
 }
 
-pub fn finish(server_state_bytes: &[u8], registration_final_message_bytes: &[u8]) -> Result<Vec<u8>, String> {
+pub fn finish(suite: u8, server_state_bytes: &[u8], registration_final_message_bytes: &[u8]) -> Result<Vec<u8>, String> {
+    if let Some(rfc_suite) = rfc::rfc_suite(suite) {
+        return rfc_suite.server_registration_finish(registration_final_message_bytes);
+    }
+    with_server_suite!(suite, finish_with_suite(server_state_bytes, registration_final_message_bytes))
+}
+
+fn finish_with_suite<CS: ServerSuite>(server_state_bytes: &[u8], registration_final_message_bytes: &[u8]) -> Result<Vec<u8>, String> {
     let server_state =
-        ServerRegistration::<AuthenticationOpaque>::deserialize(server_state_bytes).map_err(|err| format!("{}",err))?;
+        ServerRegistration::<CS>::deserialize(server_state_bytes).map_err(|err| format!("{}",err))?;
     let password_file = server_state
         .finish(RegistrationUpload::deserialize(registration_final_message_bytes).map_err(|err| format!("{}",err))?)
         .map_err(|err| format!("{}",err))?;
     Ok(password_file.serialize())
 }
 
-pub fn generate_dummy_password_file(key_pair: &KeyPair<RistrettoPoint>, username: &str) -> worker::Result<Vec<u8>>
-{
-    let mut rng = OsRng;
-    let client_registration_start_result = ClientRegistration::<AuthenticationOpaque>::start(&mut rng, b"123").map_err(|err| format!("{}",err))?;
-    
-    let server_registration_start_result = ServerRegistration::<AuthenticationOpaque>::start(
-        &mut rng,
-        client_registration_start_result.message,
-        key_pair.public(),
-    )
-    .map_err(|err| format!("{}",err))?;
+// Registration states are kept with the server key id, cipher suite and Argon2 profile the
+// registration was started with, so the password file is recorded against them even if the
+// configuration changes before it finishes
+pub struct RegistrationState<'a> {
+    pub key_id: u8,
+    pub suite: u8,
+    pub hash_profile: u8,
+    pub state: &'a [u8],
+}
 
-    let client_finish_registration_result = client_registration_start_result.state.finish(
-        &mut rng,
-        server_registration_start_result.message,
-        ClientRegistrationFinishParameters::WithIdentifiers(username.as_bytes().to_vec(), vec![]),
-    ).map_err(|err| format!("{}",err))?;
+pub fn tag_state(key_id: u8, suite: u8, hash_profile: u8, state: Vec<u8>) -> Vec<u8> {
+    let mut tagged = Vec::with_capacity(state.len() + 3);
+    tagged.push(key_id);
+    tagged.push(suite);
+    tagged.push(hash_profile);
+    tagged.extend(state);
+    tagged
+}
+
+pub fn untag_state(tagged: &[u8]) -> Option<RegistrationState<'_>> {
+    match tagged {
+        [key_id, suite, hash_profile, state @ ..] => Some(RegistrationState { key_id: *key_id, suite: *suite, hash_profile: *hash_profile, state }),
+        _ => None,
+    }
+}
 
-    let server_finish_registration_result = server_registration_start_result.state.finish(
-        client_finish_registration_result.message,
-    ).map_err(|err| format!("{}",err))?;
+#[cfg(test)]
+mod tests {
+    use super::{tag_state, untag_state};
 
-    Ok(server_finish_registration_result.serialize())
+    #[test]
+    fn state_keeps_key_suite_and_profile() {
+        let tagged = tag_state(7, 2, 1, vec![1, 2, 3]);
+        let state = untag_state(&tagged).unwrap();
+        assert_eq!((state.key_id, state.suite, state.hash_profile, state.state), (7, 2, 1, &[1u8, 2, 3][..]));
+        assert!(untag_state(&[7, 2]).is_none());
+    }
 }
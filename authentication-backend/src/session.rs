@@ -0,0 +1,163 @@
+use opaque_ke::rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+use crate::data::{AuthenticationStore, login::LoginSession, profile::{UserProfile, UserProfileMetadata}};
+
+pub const SESSION_COOKIE: &str = "SESSION";
+
+const DEFAULT_SESSION_ABSOLUTE_TTL: u64 = 7 * 24 * 60 * 60;
+const DEFAULT_SESSION_IDLE_TTL: u64 = 24 * 60 * 60;
+
+// Session expiration settings in seconds, read from the `SESSION_ABSOLUTE_TTL` and
+// `SESSION_IDLE_TTL` vars
+pub struct SessionConfig {
+    pub absolute_ttl: u64,
+    pub idle_ttl: u64,
+}
+
+impl SessionConfig {
+    pub fn new<D>(ctx: &worker::RouteContext<D>) -> Self {
+        let var = |name: &str, default: u64| ctx.var(name).ok().and_then(|value| value.to_string().parse().ok()).unwrap_or(default);
+        Self {
+            absolute_ttl: var("SESSION_ABSOLUTE_TTL", DEFAULT_SESSION_ABSOLUTE_TTL),
+            idle_ttl: var("SESSION_IDLE_TTL", DEFAULT_SESSION_IDLE_TTL),
+        }
+    }
+
+    pub fn is_expired(&self, session: &LoginSession, now: u64) -> bool {
+        session.created + self.absolute_ttl * 1000 <= now || session.last_used + self.idle_ttl * 1000 <= now
+    }
+
+    // Remaining time to live, whichever of the idle or absolute expiration comes first
+    pub fn expiration_ttl(&self, session: &LoginSession, now: u64) -> u64 {
+        let expires_at = (session.created + self.absolute_ttl * 1000).min(session.last_used + self.idle_ttl * 1000);
+        expires_at.saturating_sub(now) / 1000
+    }
+
+    // Renewing on every request would mean a KV write per request, so sessions are only renewed
+    // once a tenth of the idle time went by
+    fn needs_renewal(&self, session: &LoginSession, now: u64) -> bool {
+        session.last_used + (self.idle_ttl * 100).max(60 * 1000) <= now
+    }
+}
+
+pub struct SessionToken {
+    pub token: Vec<u8>,
+}
+
+impl SessionToken {
+    // Mints a new random session token
+    pub fn generate() -> Self {
+        let mut token = vec![0u8; 32];
+        OsRng.fill_bytes(&mut token);
+        Self { token }
+    }
+
+    // Public session identifier, the token hash so the token itself is never stored
+    pub fn id(&self) -> String {
+        base64::encode_config(Sha256::digest(&self.token), base64::URL_SAFE_NO_PAD)
+    }
+
+    // Cookie value, token is url safe base64
+    pub fn encode(&self) -> String {
+        base64::encode_config(&self.token, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(value: &str) -> Option<Self> {
+        let token = base64::decode_config(value, base64::URL_SAFE_NO_PAD).ok()?;
+        Some(Self { token })
+    }
+
+    // Reads the session from the `SESSION` cookie, falling back to an `Authorization: Bearer` header
+    // for non browser clients
+    pub fn from_request(req: &worker::Request) -> worker::Result<Option<Self>> {
+        if let Some(value) = crate::utils::cookie(req, SESSION_COOKIE)? {
+            return Ok(Self::decode(&value));
+        }
+        if let Some(authorization) = req.headers().get("Authorization")? {
+            if let Some(value) = authorization.strip_prefix("Bearer ") {
+                return Ok(Self::decode(value.trim()));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn set_cookie_header(&self, config: &SessionConfig) -> String {
+        format!("{}={}; Path=/; HttpOnly; Secure; SameSite=Strict; Max-Age={}", SESSION_COOKIE, self.encode(), config.absolute_ttl)
+    }
+
+    pub fn clear_cookie_header() -> String {
+        format!("{}=; Path=/; HttpOnly; Secure; SameSite=Strict; Max-Age=0", SESSION_COOKIE)
+    }
+}
+
+pub struct AuthenticatedSession {
+    pub session: LoginSession,
+    pub profile: UserProfile,
+    pub metadata: UserProfileMetadata,
+}
+
+// Guard for protected routes, resolves the request session to the user profile.
+// Returns `None` when the session is missing, revoked, expired or the account can't log in.
+// Sessions are renewed on use, sliding the idle expiration.
+pub async fn authenticate(req: &worker::Request, data: &dyn AuthenticationStore, config: &SessionConfig) -> worker::Result<Option<AuthenticatedSession>> {
+    let session = match SessionToken::from_request(req)? {
+        Some(session) => session,
+        None => return Ok(None),
+    };
+
+    let mut session = match data.get_login_session(&session.id()).await? {
+        Some(session) => session,
+        None => return Ok(None),
+    };
+
+    let now = crate::utils::now_millis();
+    if config.is_expired(&session, now) {
+        return Ok(None);
+    }
+
+    let (profile, metadata) = match data.get_profile(&session.username).await? {
+        Some(profile) => profile,
+        None => return Ok(None),
+    };
+
+    if metadata.l || !metadata.e {
+        return Ok(None);
+    }
+
+    if config.needs_renewal(&session, now) {
+        session.last_used = now;
+        data.set_login_session(&session, config.expiration_ttl(&session, now)).await?;
+    }
+
+    Ok(Some(AuthenticatedSession { session, profile, metadata }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SessionConfig, SessionToken};
+    use crate::data::login::LoginSession;
+
+    #[test]
+    fn session_token_roundtrip() {
+        let session = SessionToken::generate();
+        let decoded = SessionToken::decode(&session.encode()).unwrap();
+        assert_eq!(decoded.token, session.token);
+        assert_eq!(decoded.id(), session.id());
+        assert_ne!(session.id(), session.encode());
+        assert!(SessionToken::decode("not base64!").is_none());
+    }
+
+    #[test]
+    fn session_expiration() {
+        let config = SessionConfig { absolute_ttl: 1000, idle_ttl: 100 };
+        let session = LoginSession { id: "id".to_string(), username: "alice".to_string(), created: 0, last_used: 950_000 };
+        assert!(!config.is_expired(&session, 990_000));
+        assert_eq!(config.expiration_ttl(&session, 990_000), 10);
+        assert!(config.is_expired(&session, 1_000_000));
+
+        let idle = LoginSession { last_used: 0, ..session };
+        assert_eq!(config.expiration_ttl(&idle, 50_000), 50);
+        assert!(config.is_expired(&idle, 100_000));
+    }
+}
@@ -13,25 +13,79 @@ cfg_if! {
 }
 
 
-pub fn validate_username(username: &str) -> bool {
-    let username_re = unwrap_res_abort(Regex::new(r"[1-9a-z_\.\-]{3,15}$"));
-    username_re.is_match(username)
+// Current time in millis, falls back to the system clock outside of the Workers runtime
+pub fn now_millis() -> u64 {
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            worker::Date::now().as_millis()
+        } else {
+            use std::time::{SystemTime, UNIX_EPOCH};
+            SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_millis() as u64)
+        }
+    }
 }
 
-#[inline]
-pub fn unwrap_abort<T>(o: Option<T>) -> T {
-    use std::process;
-    match o {
-        Some(t) => t,
-        None => process::abort(),
+// Waits until the time in millis, the Workers runtime has no other way to sleep than `setTimeout`
+pub async fn sleep_until(millis: u64) -> worker::Result<()> {
+    let wait = millis.saturating_sub(now_millis());
+    cfg_if! {
+        if #[cfg(target_arch = "wasm32")] {
+            use worker::{js_sys::{self, Reflect}, wasm_bindgen::{JsCast, JsValue}, wasm_bindgen_futures::JsFuture};
+            let set_timeout: js_sys::Function = Reflect::get(&js_sys::global(), &JsValue::from_str("setTimeout"))?.dyn_into()?;
+            let timeout = js_sys::Promise::new(&mut |resolve, _| {
+                let _ = set_timeout.call2(&JsValue::NULL, &resolve, &JsValue::from_f64(wait as f64));
+            });
+            JsFuture::from(timeout).await?;
+        } else {
+            std::thread::sleep(std::time::Duration::from_millis(wait));
+        }
     }
+    Ok(())
+}
+
+// Value of a cookie of the request
+pub fn cookie(req: &worker::Request, name: &str) -> worker::Result<Option<String>> {
+    let cookies = match req.headers().get("Cookie")? {
+        Some(cookies) => cookies,
+        None => return Ok(None),
+    };
+    Ok(cookies
+        .split(';')
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value.to_string()))
 }
 
-#[inline]
-pub fn unwrap_res_abort<T, E>(o: Result<T, E>) -> T {
-    use std::process;
-    match o {
-        Ok(t) => t,
-        Err(_) => process::abort(),
+pub fn validate_username(username: &str) -> bool {
+    Regex::new(r"^[1-9a-z_\.\-]{3,15}$").is_ok_and(|username_re| username_re.is_match(username))
+}
+
+pub fn validate_mail(mail: &str) -> bool {
+    let mail_re = Regex::new(r#"^[a-zA-Z0-9.!#$%&'*+/=?^_`{|}~-]+@[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(?:\.[a-zA-Z0-9](?:[a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*$"#);
+    mail.len() >= 5 && mail.len() <= 100 && mail_re.is_ok_and(|mail_re| mail_re.is_match(mail))
+}
+
+// Emails are stored and compared lowercase, `Alice@example.com` is the address of `alice@example.com`
+pub fn normalize_mail(mail: &str) -> String {
+    mail.to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_mail, validate_username};
+
+    #[test]
+    fn usernames_are_matched_whole() {
+        assert!(validate_username("bob"));
+        assert!(validate_username("bob.smith-2"));
+        assert!(!validate_username("bob:evil"));
+        assert!(!validate_username("bob:abc"));
+        assert!(!validate_username("x:bob"));
+        assert!(!validate_username("bo"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn mails_are_compared_lowercase() {
+        assert_eq!(normalize_mail("Alice@Example.COM"), normalize_mail("alice@example.com"));
+    }
+}
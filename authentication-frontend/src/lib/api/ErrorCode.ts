@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export type ErrorCode = "bad_request" | "invalid_fields" | "invalid_username" | "unsupported_api_version" | "unsupported_cipher_suite" | "user_already_registered" | "registration_pending_email_confirmation" | "too_many_attempts" | "no_registration_state" | "no_login_state" | "password_file_up_to_date" | "invalid_reset_token" | "invalid_confirmation_key" | "unknown_user" | "email_not_verified" | "email_already_verified" | "email_already_in_use" | "account_locked" | "invalid_key_confirmation" | "unauthorized" | "session_not_found" | "server_key_unavailable" | "internal";
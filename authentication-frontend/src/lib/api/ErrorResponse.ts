@@ -0,0 +1,4 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { ErrorCode } from "./ErrorCode";
+
+export type ErrorResponse = { code: ErrorCode, message: string, fields?: { [key in string]?: string }, };
@@ -0,0 +1,85 @@
+import { apiVersion, apiVersionHeader } from "authentication-wasm";
+import type { EmailChangeRequest } from "$lib/api/EmailChangeRequest";
+import type { EmailConfirmationResendRequest } from "$lib/api/EmailConfirmationResendRequest";
+import type { ErrorCode } from "$lib/api/ErrorCode";
+import type { ErrorResponse } from "$lib/api/ErrorResponse";
+import type { LoginFinishRequest } from "$lib/api/LoginFinishRequest";
+import type { LoginRequest } from "$lib/api/LoginRequest";
+import type { PasswordChangeRequest } from "$lib/api/PasswordChangeRequest";
+import type { PasswordResetRegistrationRequest } from "$lib/api/PasswordResetRegistrationRequest";
+import type { PasswordResetRequest } from "$lib/api/PasswordResetRequest";
+import type { RegistrationRequest } from "$lib/api/RegistrationRequest";
+
+export const BACKEND_URL = "http://127.0.0.1:8787";
+
+// Request body of every POST endpoint, the types are generated from `authentication-api`
+type Requests = {
+	"/register/start": RegistrationRequest;
+	"/register/end": RegistrationRequest;
+	"/register/confirm/resend": EmailConfirmationResendRequest;
+	"/login/start": LoginRequest;
+	"/login/end": LoginFinishRequest;
+	"/password/change/start": PasswordChangeRequest;
+	"/password/change/end": PasswordChangeRequest;
+	"/password/upgrade/start": PasswordChangeRequest;
+	"/password/upgrade/end": PasswordChangeRequest;
+	"/password/reset/request": PasswordResetRequest;
+	"/password/reset/start": PasswordResetRegistrationRequest;
+	"/password/reset/end": PasswordResetRegistrationRequest;
+	"/account/email": EmailChangeRequest;
+};
+
+// The wasm module has to be initialized first, it knows the API version of the bodies
+export function post<P extends keyof Requests>(path: P, body: Requests[P], credentials?: RequestCredentials): Promise<Response> {
+	return fetch(`${BACKEND_URL}${path}`, {
+		method: "POST",
+		credentials,
+		body: JSON.stringify(body),
+		headers: {
+			"Content-Type": "application/json",
+			[apiVersionHeader()]: apiVersion().toString()
+		}
+	});
+}
+
+// An error answer of the backend, `code` is stable while the message may change
+export class BackendError extends Error {
+	constructor(readonly body: ErrorResponse, readonly status: number) {
+		super(body.message);
+	}
+
+	get code(): ErrorCode {
+		return this.body.code;
+	}
+}
+
+export async function backendError(response: Response): Promise<BackendError> {
+	try {
+		return new BackendError(await response.json(), response.status);
+	} catch {
+		return new BackendError({ code: "internal", message: "Server error" }, response.status);
+	}
+}
+
+// Text shown to the user, only codes a user can act on are worded differently from the default
+const errorMessages: Partial<Record<ErrorCode, string>> = {
+	invalid_fields: "Some fields are invalid",
+	user_already_registered: "This username is taken",
+	registration_pending_email_confirmation: "This username is waiting for its email to be confirmed",
+	too_many_attempts: "Too many attempts, please try again later",
+	invalid_reset_token: "The reset link is invalid or expired",
+	invalid_confirmation_key: "The confirmation link is invalid or expired",
+	email_not_verified: "Please confirm your email first",
+	email_already_in_use: "This email is used by another account",
+	account_locked: "This account is locked",
+	invalid_key_confirmation: "Wrong username or password",
+	unauthorized: "Please log in again",
+	unsupported_api_version: "Please reload the page, the server was updated"
+};
+
+export function describeError(e: unknown): string {
+	if (e instanceof BackendError) {
+		return errorMessages[e.code] ?? e.message;
+	}
+	return e instanceof Error ? e.message : String(e);
+}
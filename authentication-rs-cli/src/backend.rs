@@ -0,0 +1,93 @@
+use authentication_api::{ErrorResponse, LoginFinishRequest, LoginFinishResponse, LoginRequest, PasswordChangeRequest, RegistrationRequest, API_VERSION, API_VERSION_HEADER};
+use authentication_rs_lib::client::{Login, Registration};
+use serde::Serialize;
+
+pub struct Session {
+    pub username: String,
+    // token of the `SESSION` cookie, also accepted as a bearer token
+    pub token: String,
+}
+
+// Runs the client side of the OPAQUE flows against a backend over HTTP
+pub struct Backend {
+    url: String,
+    agent: ureq::Agent,
+}
+
+fn error(err: ureq::Error) -> String {
+    match err {
+        ureq::Error::Status(status, response) => match response.into_json::<ErrorResponse>() {
+            Ok(body) => format!("Server error {} ({:?}): {}", status, body.code, body.message),
+            Err(_) => format!("Server error {}", status),
+        },
+        err => err.to_string(),
+    }
+}
+
+impl Backend {
+    pub fn new(url: &str) -> Self {
+        Self { url: url.trim_end_matches('/').to_string(), agent: ureq::agent() }
+    }
+
+    fn post(&self, path: &str, token: Option<&str>, body: impl Serialize) -> Result<ureq::Response, String> {
+        let mut request = self.agent.post(&format!("{}{}", self.url, path)).set(API_VERSION_HEADER, &API_VERSION.to_string());
+        if let Some(token) = token {
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        request.send_json(body).map_err(error)
+    }
+
+    fn text(response: ureq::Response) -> Result<String, String> {
+        response.into_string().map_err(|err| err.to_string())
+    }
+
+    // The account can only be used after confirming the email
+    pub fn register(&self, username: &str, mail: &str, password: &str) -> Result<(), String> {
+        let registration = Registration::start(password)?;
+        let server_start = Self::text(self.post("/register/start", None, RegistrationRequest { username: username.to_string(), mail: mail.to_string(), request: registration.server_request().to_string() })?)?;
+        let request = registration.finish(username, &server_start)?;
+        self.post("/register/end", None, RegistrationRequest { username: username.to_string(), mail: mail.to_string(), request })?;
+        Ok(())
+    }
+
+    pub fn login(&self, username: &str, password: &str) -> Result<Session, String> {
+        let login = Login::start(username, password)?;
+        let server_start = Self::text(self.post("/login/start", None, LoginRequest { username: username.to_string(), request: login.server_request().to_string() })?)?;
+        let login_final = login.finish(username, &server_start)?;
+
+        let response = self.post("/login/end", None, LoginFinishRequest {
+            username: username.to_string(),
+            request: login_final.server_request().to_string(),
+            confirmation: login_final.confirmation().to_string(),
+        })?;
+        let token = response.all("Set-Cookie").into_iter()
+            .filter_map(|cookie| cookie.split(';').next())
+            .filter_map(|cookie| cookie.trim().strip_prefix("SESSION="))
+            .map(String::from)
+            .next()
+            .ok_or_else(|| "No session in the login response".to_string())?;
+        let finish: LoginFinishResponse = response.into_json().map_err(|err| err.to_string())?;
+
+        if !login_final.verify_server(&finish.confirmation)? {
+            return Err("The server failed the key confirmation".to_string());
+        }
+
+        if finish.upgrade {
+            // Not fatal, the upgrade is asked for again on the next login
+            if let Err(err) = self.upgrade_password_file(username, password, &token) {
+                eprintln!("Password file upgrade failed: {}", err);
+            }
+        }
+
+        Ok(Session { username: username.to_string(), token })
+    }
+
+    // Registers the password file again with the newest server key and Argon2 profile
+    fn upgrade_password_file(&self, username: &str, password: &str, token: &str) -> Result<(), String> {
+        let registration = Registration::start(password)?;
+        let server_start = Self::text(self.post("/password/upgrade/start", Some(token), PasswordChangeRequest { request: registration.server_request().to_string() })?)?;
+        let request = registration.finish(username, &server_start)?;
+        self.post("/password/upgrade/end", Some(token), PasswordChangeRequest { request })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use authentication_rs_lib::AuthenticationOpaque;
+use opaque_ke::{ciphersuite::CipherSuite, keypair::KeyPair, rand::rngs::OsRng};
+use serde_json::json;
+
+pub type ServerKeyPair = KeyPair<<AuthenticationOpaque as CipherSuite>::Group>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum OutputFormat {
+    Base64,
+    Hex,
+    Json,
+    // a `wrangler secret put` command with the private key as payload
+    Wrangler,
+}
+
+pub fn generate() -> ServerKeyPair {
+    AuthenticationOpaque::generate_random_keypair(&mut OsRng)
+}
+
+// Reads a private key as stored in the `SERVER_KEYPAIR` secret, hex is accepted too
+pub fn parse_private_key(value: &str) -> Result<ServerKeyPair, String> {
+    let value = value.trim();
+    let bytes = match hex::decode(value) {
+        Ok(bytes) if value.len() == 64 => bytes,
+        _ => base64::decode(value).map_err(|err| format!("Invalid base64: {}", err))?,
+    };
+    if bytes.len() != 32 {
+        return Err(format!("Invalid key length, expected 32 bytes but got {}", bytes.len()));
+    }
+    ServerKeyPair::from_private_key_slice(&bytes).map_err(|err| format!("Invalid private key: {}", err))
+}
+
+fn encode(bytes: &[u8], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Hex => hex::encode(bytes),
+        _ => base64::encode(bytes),
+    }
+}
+
+// Formats a keypair, without the private key when only the public key was asked for. The
+// wrangler payload is always the private key.
+pub fn format_key_pair(key_pair: &ServerKeyPair, key_id: Option<u8>, include_private: bool, format: OutputFormat) -> String {
+    let private_key = key_pair.private().to_arr();
+    let public_key = key_pair.public().to_arr();
+
+    match format {
+        OutputFormat::Base64 | OutputFormat::Hex => {
+            let mut lines = vec![];
+            if let Some(key_id) = key_id {
+                lines.push(format!("Key id: {}", key_id));
+            }
+            if include_private {
+                lines.push(format!("Private key: {}", encode(&private_key, format)));
+            }
+            lines.push(format!("Public key: {}", encode(&public_key, format)));
+            lines.join("\n")
+        }
+        OutputFormat::Json => {
+            let mut value = json!({ "public_key": base64::encode(public_key) });
+            if let Some(key_id) = key_id {
+                value["key_id"] = json!(key_id);
+            }
+            if include_private {
+                value["private_key"] = json!(base64::encode(private_key));
+            }
+            value.to_string()
+        }
+        OutputFormat::Wrangler => {
+            // Keys with an id belong in the key ring, the entry has to be merged with the keys
+            // already in the secret
+            match key_id {
+                Some(key_id) => format!("echo '{}' | wrangler secret put SERVER_KEYRING", json!({ key_id.to_string(): base64::encode(private_key) })),
+                None => format!("echo '{}' | wrangler secret put SERVER_KEYPAIR", base64::encode(private_key)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_key_pair, generate, parse_private_key, OutputFormat};
+
+    #[test]
+    fn private_key_roundtrip() {
+        let key_pair = generate();
+        let base64_key = base64::encode(key_pair.private().to_arr());
+        let hex_key = hex::encode(key_pair.private().to_arr());
+
+        assert_eq!(parse_private_key(&base64_key).unwrap().public(), key_pair.public());
+        assert_eq!(parse_private_key(&hex_key).unwrap().public(), key_pair.public());
+        assert!(parse_private_key("not a key").is_err());
+        assert!(parse_private_key(&base64::encode([1u8; 16])).is_err());
+    }
+
+    #[test]
+    fn public_output_has_no_private_key() {
+        let key_pair = generate();
+        let private_key = base64::encode(key_pair.private().to_arr());
+
+        let output = format_key_pair(&key_pair, Some(3), false, OutputFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(value["key_id"], 3);
+        assert!(value.get("private_key").is_none());
+
+        let output = format_key_pair(&key_pair, Some(3), true, OutputFormat::Wrangler);
+        assert!(output.contains(&format!(r#"{{"3":"{}"}}"#, private_key)));
+    }
+}
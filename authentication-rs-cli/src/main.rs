@@ -1,11 +1,120 @@
-use authentication_rs_lib::AuthenticationOpaque;
-use opaque_ke::{
-    ciphersuite::CipherSuite, rand::rngs::OsRng
-};
+mod backend;
+mod keys;
+
+use clap::{Parser, Subcommand};
+
+use keys::OutputFormat;
+
+#[derive(Parser)]
+#[clap(version, about)]
+struct Cli {
+    /// Output format
+    #[clap(short, long, arg_enum, global = true, default_value = "base64")]
+    format: OutputFormat,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a new server keypair
+    Generate {
+        /// Key id in the `SERVER_KEYRING` secret, without it the key is for `SERVER_KEYPAIR`
+        #[clap(short, long)]
+        id: Option<u8>,
+    },
+    /// Print the public key of a private key
+    Public {
+        /// Private key, base64 as in the `SERVER_KEYPAIR` secret or hex
+        private_key: String,
+    },
+    /// Check a `SERVER_KEYPAIR` value
+    Validate {
+        /// Private key, base64 as in the `SERVER_KEYPAIR` secret or hex
+        private_key: String,
+    },
+    /// Register a new account on a backend
+    Register {
+        /// Backend URL
+        #[clap(short, long, default_value = "http://127.0.0.1:8787")]
+        url: String,
+        #[clap(short = 'n', long)]
+        username: String,
+        #[clap(short, long)]
+        mail: String,
+        /// Asked for when not given
+        #[clap(short, long)]
+        password: Option<String>,
+    },
+    /// Login on a backend and print the session
+    Login {
+        /// Backend URL
+        #[clap(short, long, default_value = "http://127.0.0.1:8787")]
+        url: String,
+        #[clap(short = 'n', long)]
+        username: String,
+        /// Asked for when not given
+        #[clap(short, long)]
+        password: Option<String>,
+    },
+}
+
+fn password(password: Option<String>) -> String {
+    password.unwrap_or_else(|| rpassword::prompt_password("Password: ").unwrap_or_else(|err| {
+        eprintln!("Couldn't read the password: {}", err);
+        std::process::exit(1);
+    }))
+}
+
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    })
+}
 
 fn main() {
-    println!("Will generate new server  key 🔐 :");
-    let mut rng = OsRng;
-    let server_kp = AuthenticationOpaque::generate_random_keypair(&mut rng);
-    println!("New key: {}", base64::encode( server_kp.private().to_arr().to_vec()));
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Generate { id } => {
+            eprintln!("Will generate new server key 🔐 :");
+            let key_pair = keys::generate();
+            println!("{}", keys::format_key_pair(&key_pair, id, true, cli.format));
+        }
+        Command::Public { .. } if cli.format == OutputFormat::Wrangler => {
+            eprintln!("The wrangler payload is the private key, use `validate` to get it");
+            std::process::exit(1);
+        }
+        Command::Public { private_key } => match keys::parse_private_key(&private_key) {
+            Ok(key_pair) => println!("{}", keys::format_key_pair(&key_pair, None, false, cli.format)),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+        Command::Validate { private_key } => match keys::parse_private_key(&private_key) {
+            Ok(key_pair) => {
+                eprintln!("Valid server key ✅");
+                // The private key is only repeated for the wrangler payload
+                println!("{}", keys::format_key_pair(&key_pair, None, cli.format == OutputFormat::Wrangler, cli.format));
+            }
+            Err(err) => {
+                eprintln!("Invalid server key ❌ : {}", err);
+                std::process::exit(1);
+            }
+        },
+        Command::Register { url, username, mail, password: password_arg } => {
+            exit_on_error(backend::Backend::new(&url).register(&username, &mail, &password(password_arg)));
+            eprintln!("Registered, confirm the email sent to {} to be able to login ✉️", mail);
+        }
+        Command::Login { url, username, password: password_arg } => {
+            let session = exit_on_error(backend::Backend::new(&url).login(&username, &password(password_arg)));
+            match cli.format {
+                OutputFormat::Json => println!("{}", serde_json::json!({ "username": session.username, "session": session.token })),
+                _ => println!("Username: {}\nSession: {}", session.username, session.token),
+            }
+        }
+    }
 }
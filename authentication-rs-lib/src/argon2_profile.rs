@@ -0,0 +1,58 @@
+// Named, versioned Argon2 parameters. The slow hash runs on the client, so the server sends the
+// profile id of a password file with its registration and login messages (see
+// `cipher_suite::tag_message`), and a profile can't be changed once password files were made with
+// it, raising the cost means adding a new one.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Profile {
+    pub id: u8,
+    pub name: &'static str,
+    // memory cost in KiB
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u32,
+    pub salt: &'static [u8],
+}
+
+// Parameters every password file was made with before profiles existed
+pub const LEGACY_PROFILE: u8 = 0;
+// Used for new password files unless the backend is configured otherwise
+pub const DEFAULT_PROFILE: u8 = 1;
+
+pub const PROFILES: &[Argon2Profile] = &[
+    Argon2Profile { id: LEGACY_PROFILE, name: "legacy", memory_cost: 2048, time_cost: 4, parallelism: 2, salt: &[0; argon2::MIN_SALT_LEN] },
+    // OWASP recommendation for Argon2id
+    Argon2Profile { id: 1, name: "interactive", memory_cost: 15 * 1024, time_cost: 2, parallelism: 1, salt: b"authentication-opaque-argon2id-1" },
+];
+
+#[must_use]
+pub fn profile(id: u8) -> Option<&'static Argon2Profile> {
+    PROFILES.iter().find(|profile| profile.id == id)
+}
+
+#[must_use]
+pub fn profile_by_name(name: &str) -> Option<&'static Argon2Profile> {
+    PROFILES.iter().find(|profile| profile.name == name)
+}
+
+// Argon2id of `input` with the parameters of a profile, fills all of `output`
+pub fn hash(profile: &Argon2Profile, input: &[u8], output: &mut [u8]) -> Result<(), argon2::Error> {
+    let params = argon2::Params::new(profile.memory_cost, profile.time_cost, profile.parallelism, Some(output.len()))?;
+    argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params).hash_password_into(input, profile.salt, output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{profile, profile_by_name, DEFAULT_PROFILE, LEGACY_PROFILE, PROFILES};
+
+    #[test]
+    fn profiles_are_unique() {
+        for (index, argon2_profile) in PROFILES.iter().enumerate() {
+            assert_eq!(profile(argon2_profile.id), Some(argon2_profile));
+            assert_eq!(profile_by_name(argon2_profile.name), Some(argon2_profile));
+            assert!(PROFILES[index + 1..].iter().all(|other| other.id != argon2_profile.id));
+        }
+        assert!(profile(LEGACY_PROFILE).is_some());
+        assert!(profile(DEFAULT_PROFILE).is_some());
+    }
+}
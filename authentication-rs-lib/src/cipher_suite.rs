@@ -0,0 +1,143 @@
+// OPAQUE cipher suites a password file can be registered with. The suite id is stored with every
+// password file, the P-256 and PBKDF2 suites are only available with their cargo feature.
+// Suites 0 and 2 are the pre-RFC protocol of opaque-ke 1, they're only kept so their password
+// files can still log in and get registered again with an RFC suite (see `rfc`).
+// Clients start the flows with a request for every suite they know and the server answers with
+// the one it picked, so both sides only have to agree on the ids.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CipherSuiteInfo {
+    pub id: u8,
+    pub name: &'static str,
+    // the slow hash is Argon2id with the profile sent by the server, otherwise the profile is ignored
+    pub argon2: bool,
+    // RFC 9807 OPAQUE rather than the draft opaque-ke 1 implements
+    pub rfc: bool,
+}
+
+pub const RISTRETTO255_ARGON2: u8 = 0;
+// 1 was a P-256 suite of opaque-ke 1 with a group of our own, replaced by `RFC_P256_ARGON2` and
+// not reused
+// `pbkdf2` feature, for clients that can't spare the memory Argon2 needs
+pub const RISTRETTO255_PBKDF2: u8 = 2;
+
+pub const RFC_RISTRETTO255_ARGON2: u8 = 3;
+pub const RFC_P256_ARGON2: u8 = 4;
+pub const RFC_RISTRETTO255_PBKDF2: u8 = 5;
+
+// Suite of every password file made before suites existed
+pub const LEGACY_SUITE: u8 = RISTRETTO255_ARGON2;
+// Used for new password files unless the backend is configured otherwise
+pub const DEFAULT_SUITE: u8 = RFC_RISTRETTO255_ARGON2;
+
+const ALL_SUITES: &[CipherSuiteInfo] = &[
+    CipherSuiteInfo { id: RISTRETTO255_ARGON2, name: "ristretto255-sha512-argon2id", argon2: true, rfc: false },
+    CipherSuiteInfo { id: RISTRETTO255_PBKDF2, name: "ristretto255-sha512-pbkdf2", argon2: false, rfc: false },
+    CipherSuiteInfo { id: RFC_RISTRETTO255_ARGON2, name: "rfc-ristretto255-sha512-argon2id", argon2: true, rfc: true },
+    CipherSuiteInfo { id: RFC_P256_ARGON2, name: "rfc-p256-sha256-argon2id", argon2: true, rfc: true },
+    CipherSuiteInfo { id: RFC_RISTRETTO255_PBKDF2, name: "rfc-ristretto255-sha512-pbkdf2", argon2: false, rfc: true },
+];
+
+fn enabled(id: u8) -> bool {
+    let p256 = id == RFC_P256_ARGON2;
+    let pbkdf2 = id == RISTRETTO255_PBKDF2 || id == RFC_RISTRETTO255_PBKDF2;
+    (!p256 || cfg!(feature = "p256")) && (!pbkdf2 || cfg!(feature = "pbkdf2"))
+}
+
+// Suites compiled in
+pub fn suites() -> impl Iterator<Item = &'static CipherSuiteInfo> {
+    ALL_SUITES.iter().filter(|suite| enabled(suite.id))
+}
+
+#[must_use]
+pub fn suite(id: u8) -> Option<&'static CipherSuiteInfo> {
+    suites().find(|suite| suite.id == id)
+}
+
+#[must_use]
+pub fn suite_by_name(name: &str) -> Option<&'static CipherSuiteInfo> {
+    suites().find(|suite| suite.name == name)
+}
+
+// Prefixes a server message with the suite and the Argon2 profile the client has to finish with
+#[must_use]
+pub fn tag_message(suite: u8, profile: u8, message: &[u8]) -> Vec<u8> {
+    let mut tagged = Vec::with_capacity(message.len() + 2);
+    tagged.push(suite);
+    tagged.push(profile);
+    tagged.extend_from_slice(message);
+    tagged
+}
+
+#[must_use]
+pub fn untag_message(tagged: &[u8]) -> Option<(u8, u8, &[u8])> {
+    match tagged {
+        [suite, profile, message @ ..] => Some((*suite, *profile, message)),
+        _ => None,
+    }
+}
+
+// Client requests are a list of suite id, big endian u16 length and message
+#[must_use]
+pub fn encode_offers(offers: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for (suite, message) in offers {
+        encoded.push(*suite);
+        encoded.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        encoded.extend_from_slice(message);
+    }
+    encoded
+}
+
+#[must_use]
+pub fn decode_offers(mut encoded: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut offers = Vec::new();
+    while let [suite, high, low, rest @ ..] = encoded {
+        let len = usize::from(u16::from_be_bytes([*high, *low]));
+        if rest.len() < len {
+            return None;
+        }
+        offers.push((*suite, &rest[..len]));
+        encoded = &rest[len..];
+    }
+    if !encoded.is_empty() || offers.is_empty() {
+        return None;
+    }
+    Some(offers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_offers, encode_offers, suite, suite_by_name, tag_message, untag_message, DEFAULT_SUITE, LEGACY_SUITE};
+
+    #[test]
+    fn offers_round_trip() {
+        let offers = vec![(0, vec![1, 2, 3]), (2, vec![]), (1, vec![4; 300])];
+        let encoded = encode_offers(&offers);
+        let decoded = decode_offers(&encoded).unwrap();
+        assert_eq!(decoded.len(), 3);
+        for ((suite, message), (decoded_suite, decoded_message)) in offers.iter().zip(decoded) {
+            assert_eq!(*suite, decoded_suite);
+            assert_eq!(&message[..], decoded_message);
+        }
+
+        assert!(decode_offers(&encoded[..encoded.len() - 1]).is_none());
+        assert!(decode_offers(&[]).is_none());
+    }
+
+    #[test]
+    fn messages_keep_suite_and_profile() {
+        let tagged = tag_message(2, 1, &[7, 8]);
+        assert_eq!(untag_message(&tagged), Some((2, 1, &[7u8, 8][..])));
+        assert_eq!(untag_message(&[2]), None);
+    }
+
+    #[test]
+    fn default_and_legacy_suites_are_always_available() {
+        let default = suite(DEFAULT_SUITE).unwrap();
+        assert_eq!(suite_by_name(default.name), Some(default));
+        assert!(default.rfc);
+        assert!(!suite(LEGACY_SUITE).unwrap().rfc);
+        assert!(suite(u8::MAX).is_none());
+    }
+}
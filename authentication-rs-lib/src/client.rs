@@ -0,0 +1,268 @@
+use opaque_ke::{ciphersuite::CipherSuite, rand::rngs::OsRng, ClientLogin, ClientLoginFinishParameters, ClientLoginStartParameters, ClientRegistration, ClientRegistrationFinishParameters, CredentialResponse, RegistrationResponse};
+
+#[cfg(feature = "pbkdf2")]
+use crate::Pbkdf2Opaque;
+use crate::{AuthenticationOpaqueProfile, argon2_profile, cipher_suite, rfc::{self, KeyStretch}, key_confirmation::{confirmation_tag, verify_confirmation_tag, CLIENT_CONFIRMATION_LABEL, SERVER_CONFIRMATION_LABEL}};
+
+// Client side of the OPAQUE flows, messages are exchanged with the backend as base64 strings.
+// The flows start with a request for every suite of `cipher_suite::suites`, server messages start
+// with the suite and Argon2 profile it picked, and only the matching client state is finished.
+// RFC suites need the password again to finish, so it's kept until then.
+
+// Calls `$function::<CipherSuite>` for a legacy suite id and Argon2 profile, every profile of
+// `argon2_profile::PROFILES` needs an arm for each suite hashing with Argon2
+macro_rules! with_legacy_suite {
+    ($suite:expr, $profile:expr, $function:ident($($arg:expr),*)) => {
+        match ($suite, $profile) {
+            (cipher_suite::RISTRETTO255_ARGON2, 0) => $function::<AuthenticationOpaqueProfile<0>>($($arg),*),
+            (cipher_suite::RISTRETTO255_ARGON2, 1) => $function::<AuthenticationOpaqueProfile<1>>($($arg),*),
+            #[cfg(feature = "pbkdf2")]
+            (cipher_suite::RISTRETTO255_PBKDF2, _) => $function::<Pbkdf2Opaque>($($arg),*),
+            (suite, profile) => Err(format!("Unknown cipher suite {} with Argon2 profile {}", suite, profile)),
+        }
+    };
+}
+
+// Returns the client state and the request
+fn start_registration<CS: CipherSuite>(password: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let mut client_rng = OsRng;
+    let client_registration_start_result =
+        ClientRegistration::<CS>::start(&mut client_rng, password.as_bytes()).map_err(|err| err.to_string())?;
+    Ok((client_registration_start_result.state.serialize(), client_registration_start_result.message.serialize()))
+}
+
+fn finish_registration<CS: CipherSuite>(state: &[u8], server_response: &[u8], username: &str) -> Result<Vec<u8>, String> {
+    let mut client_rng = OsRng;
+    let state = ClientRegistration::<CS>::deserialize(state).map_err(|err| err.to_string())?;
+    let client_finish_registration_result = state.finish(
+        &mut client_rng,
+        RegistrationResponse::deserialize(server_response).map_err(|err| err.to_string())?,
+        ClientRegistrationFinishParameters::WithIdentifiers(username.as_bytes().to_vec(), vec![]),
+    ).map_err(|err| err.to_string())?;
+    Ok(client_finish_registration_result.message.serialize())
+}
+
+// Returns the client state and the request
+fn start_login<CS: CipherSuite>(username: &str, password: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let mut client_rng = OsRng;
+    let client_login_start_result =
+        ClientLogin::<CS>::start(&mut client_rng, password.as_bytes(), ClientLoginStartParameters::WithInfo(username.as_bytes().to_vec())).map_err(|err| err.to_string())?;
+    Ok((
+        client_login_start_result.state.serialize().map_err(|err| err.to_string())?,
+        client_login_start_result.message.serialize().map_err(|err| err.to_string())?,
+    ))
+}
+
+// Returns the credential finalization and the session key
+fn finish_login<CS: CipherSuite>(state: &[u8], server_response: &[u8], username: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let state = ClientLogin::<CS>::deserialize(state).map_err(|err| err.to_string())?;
+    let client_finish_login_result = state.finish(
+        CredentialResponse::deserialize(server_response).map_err(|err| err.to_string())?,
+        ClientLoginFinishParameters::WithIdentifiers(username.as_bytes().to_vec(), vec![]),
+    ).map_err(|err| err.to_string())?;
+    Ok((client_finish_login_result.message.serialize().map_err(|err| err.to_string())?, client_finish_login_result.session_key))
+}
+
+// Client states by suite and the encoded request offering all of them
+struct SuiteStates {
+    states: Vec<(u8, Vec<u8>)>,
+    server_request: String,
+}
+
+impl SuiteStates {
+    fn start(start: impl Fn(u8) -> Result<(Vec<u8>, Vec<u8>), String>) -> Result<Self, String> {
+        let mut states = Vec::new();
+        let mut offers = Vec::new();
+        for suite in cipher_suite::suites() {
+            let (state, request) = start(suite.id)?;
+            states.push((suite.id, state));
+            offers.push((suite.id, request));
+        }
+        Ok(SuiteStates { states, server_request: base64::encode(cipher_suite::encode_offers(&offers)) })
+    }
+
+    fn state(&self, suite: u8) -> Result<&[u8], String> {
+        let (_, state) = self.states.iter().find(|(id, _)| *id == suite).ok_or_else(|| format!("Server picked a cipher suite that wasn't offered: {}", suite))?;
+        Ok(state)
+    }
+}
+
+// Key stretching function the server asked an RFC suite to finish with
+fn key_stretch(suite: u8, profile: u8) -> Result<KeyStretch, String> {
+    KeyStretch::new(cipher_suite::suite(suite).ok_or_else(|| format!("Unknown cipher suite {}", suite))?, profile)
+}
+
+// Returns the suite, the Argon2 profile and the message
+fn decode_server_message(server_response: &str) -> Result<(u8, u8, Vec<u8>), String> {
+    let server_response_bytes = base64::decode(server_response).map_err(|err| err.to_string())?;
+    let (suite, profile, message) = cipher_suite::untag_message(&server_response_bytes).ok_or_else(|| "Empty server response".to_string())?;
+    Ok((suite, profile, message.to_vec()))
+}
+
+pub struct Registration {
+    states: SuiteStates,
+    password: String,
+}
+
+impl Registration {
+    pub fn start(password: &str) -> Result<Self, String> {
+        let states = SuiteStates::start(|suite| match rfc::rfc_suite(suite) {
+            Some(rfc_suite) => rfc_suite.client_registration_start(password),
+            None => with_legacy_suite!(suite, argon2_profile::LEGACY_PROFILE, start_registration(password)),
+        })?;
+        Ok(Registration { states, password: password.to_string() })
+    }
+
+    #[must_use]
+    pub fn server_request(&self) -> &str {
+        &self.states.server_request
+    }
+
+    // Returns the registration upload for the server
+    pub fn finish(self, username: &str, server_response: &str) -> Result<String, String> {
+        let (suite, profile, server_response) = decode_server_message(server_response)?;
+        let state = self.states.state(suite)?;
+        let upload = match rfc::rfc_suite(suite) {
+            Some(rfc_suite) => rfc_suite.client_registration_finish(state, &self.password, &server_response, username, &key_stretch(suite, profile)?)?,
+            None => with_legacy_suite!(suite, profile, finish_registration(state, &server_response, username))?,
+        };
+        Ok(base64::encode(upload))
+    }
+}
+
+pub struct Login {
+    states: SuiteStates,
+    password: String,
+}
+
+impl Login {
+    pub fn start(username: &str, password: &str) -> Result<Self, String> {
+        let states = SuiteStates::start(|suite| match rfc::rfc_suite(suite) {
+            Some(rfc_suite) => rfc_suite.client_login_start(password),
+            None => with_legacy_suite!(suite, argon2_profile::LEGACY_PROFILE, start_login(username, password)),
+        })?;
+        Ok(Login { states, password: password.to_string() })
+    }
+
+    #[must_use]
+    pub fn server_request(&self) -> &str {
+        &self.states.server_request
+    }
+
+    pub fn finish(self, username: &str, server_response: &str) -> Result<LoginFinal, String> {
+        let (suite, profile, server_response) = decode_server_message(server_response)?;
+        let state = self.states.state(suite)?;
+        let (server_request, session_key) = match rfc::rfc_suite(suite) {
+            Some(rfc_suite) => rfc_suite.client_login_finish(state, &self.password, &server_response, username, &key_stretch(suite, profile)?)?,
+            None => with_legacy_suite!(suite, profile, finish_login(state, &server_response, username))?,
+        };
+        Ok(LoginFinal {
+            server_request: base64::encode(server_request),
+            confirmation: base64::encode(confirmation_tag(&session_key, CLIENT_CONFIRMATION_LABEL, username)),
+            username: username.to_string(),
+            session_key,
+        })
+    }
+}
+
+pub struct LoginFinal {
+    server_request: String,
+    confirmation: String,
+    username: String,
+    // never sent, only used to check the server confirmation
+    session_key: Vec<u8>,
+}
+
+impl LoginFinal {
+    #[must_use]
+    pub fn server_request(&self) -> &str {
+        &self.server_request
+    }
+
+    #[must_use]
+    pub fn confirmation(&self) -> &str {
+        &self.confirmation
+    }
+
+    // Checks the server proved it derived the same session key
+    pub fn verify_server(&self, server_confirmation: &str) -> Result<bool, String> {
+        let server_confirmation = base64::decode(server_confirmation).map_err(|err| err.to_string())?;
+        Ok(verify_confirmation_tag(&self.session_key, SERVER_CONFIRMATION_LABEL, &self.username, &server_confirmation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opaque_ke::{ciphersuite::CipherSuite, keypair::KeyPair, rand::rngs::OsRng, CredentialFinalization, CredentialRequest, RegistrationRequest, RegistrationUpload, ServerLogin, ServerLoginStartParameters, ServerRegistration};
+
+    use super::{Login, Registration};
+    use crate::{AuthenticationOpaque, argon2_profile::PROFILES, cipher_suite::{self, decode_offers, tag_message}, rfc::rfc_suite, key_confirmation::{confirmation_tag, CLIENT_CONFIRMATION_LABEL, SERVER_CONFIRMATION_LABEL}};
+
+    #[test]
+    fn register_and_login_with_every_suite_and_profile() {
+        for suite in cipher_suite::suites() {
+            let profiles = if suite.argon2 { PROFILES } else { &PROFILES[..1] };
+            for profile in profiles {
+                match suite.id {
+                    _ if suite.rfc => register_and_login_rfc(suite.id, profile.id),
+                    _ => register_and_login::<AuthenticationOpaque>(suite.id, profile.id),
+                }
+            }
+        }
+    }
+
+    fn offered(request: &str, suite: u8) -> Vec<u8> {
+        let request = base64::decode(request).unwrap();
+        decode_offers(&request).unwrap().into_iter().find(|(id, _)| *id == suite).unwrap().1.to_vec()
+    }
+
+    fn register_and_login<CS: CipherSuite>(suite: u8, profile: u8) {
+        let mut rng = OsRng;
+        let server_kp: KeyPair<CS::Group> = CS::generate_random_keypair(&mut rng);
+
+        let registration = Registration::start("password").unwrap();
+        let server_registration = ServerRegistration::<CS>::start(
+            &mut rng,
+            RegistrationRequest::deserialize(&offered(registration.server_request(), suite)).unwrap(),
+            server_kp.public(),
+        ).unwrap();
+        let upload = registration.finish("alice", &base64::encode(tag_message(suite, profile, &server_registration.message.serialize()))).unwrap();
+        let password_file = server_registration.state.finish(RegistrationUpload::deserialize(&base64::decode(upload).unwrap()).unwrap()).unwrap();
+
+        let login = Login::start("alice", "password").unwrap();
+        let server_login = ServerLogin::start(
+            &mut rng,
+            password_file,
+            server_kp.private(),
+            CredentialRequest::<CS>::deserialize(&offered(login.server_request(), suite)).unwrap(),
+            ServerLoginStartParameters::WithIdentifiers(b"alice".to_vec(), vec![]),
+        ).unwrap();
+        let login_final = login.finish("alice", &base64::encode(tag_message(suite, profile, &server_login.message.serialize().unwrap()))).unwrap();
+        let session_key = server_login.state.finish(CredentialFinalization::deserialize(&base64::decode(login_final.server_request()).unwrap()).unwrap()).unwrap().session_key;
+
+        assert_eq!(login_final.confirmation(), base64::encode(confirmation_tag(&session_key, CLIENT_CONFIRMATION_LABEL, "alice")));
+        assert!(login_final.verify_server(&base64::encode(confirmation_tag(&session_key, SERVER_CONFIRMATION_LABEL, "alice"))).unwrap());
+        assert!(!login_final.verify_server(&base64::encode([0u8; 64])).unwrap());
+    }
+
+    fn register_and_login_rfc(suite: u8, profile: u8) {
+        let server = rfc_suite(suite).unwrap();
+        let server_key = [5; 32];
+
+        let registration = Registration::start("password").unwrap();
+        let response = server.server_registration_start(&server_key, &offered(registration.server_request(), suite), "alice").unwrap();
+        let upload = registration.finish("alice", &base64::encode(tag_message(suite, profile, &response))).unwrap();
+        let password_file = server.server_registration_finish(&base64::decode(upload).unwrap()).unwrap();
+
+        let login = Login::start("alice", "password").unwrap();
+        let (state, response) = server.server_login_start(&server_key, Some(&password_file), &offered(login.server_request(), suite), "alice").unwrap();
+        let login_final = login.finish("alice", &base64::encode(tag_message(suite, profile, &response))).unwrap();
+        let session_key = server.server_login_finish(&state, &base64::decode(login_final.server_request()).unwrap(), "alice").unwrap();
+
+        assert!(login_final.verify_server(&base64::encode(confirmation_tag(&session_key, SERVER_CONFIRMATION_LABEL, "alice"))).unwrap());
+
+        let wrong_login = Login::start("alice", "wrong password").unwrap();
+        let (_, response) = server.server_login_start(&server_key, Some(&password_file), &offered(wrong_login.server_request(), suite), "alice").unwrap();
+        assert!(wrong_login.finish("alice", &base64::encode(tag_message(suite, profile, &response))).is_err());
+    }
+}
@@ -0,0 +1,42 @@
+use hmac::{Hmac, Mac, NewMac};
+
+type HmacSha512 = Hmac<sha2::Sha512>;
+
+// Labels used to domain separate the client and the server confirmation tags
+pub const CLIENT_CONFIRMATION_LABEL: &[u8] = b"OPAQUE-KeyConfirmation-Client";
+pub const SERVER_CONFIRMATION_LABEL: &[u8] = b"OPAQUE-KeyConfirmation-Server";
+
+fn mac(session_key: &[u8], label: &[u8], username: &str) -> HmacSha512 {
+    // HMAC accepts keys of any length, so this can't fail
+    let mut mac = HmacSha512::new_from_slice(session_key).expect("HMAC can take key of any size");
+    mac.update(label);
+    mac.update(username.as_bytes());
+    mac
+}
+
+// Computes the key confirmation tag proving possession of the OPAQUE session key
+// without ever revealing it
+#[must_use]
+pub fn confirmation_tag(session_key: &[u8], label: &[u8], username: &str) -> Vec<u8> {
+    mac(session_key, label, username).finalize().into_bytes().to_vec()
+}
+
+// Verifies a key confirmation tag in constant time
+#[must_use]
+pub fn verify_confirmation_tag(session_key: &[u8], label: &[u8], username: &str, tag: &[u8]) -> bool {
+    mac(session_key, label, username).verify(tag).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confirmation_tag_roundtrip() {
+        let tag = confirmation_tag(b"session key", CLIENT_CONFIRMATION_LABEL, "alice");
+        assert!(verify_confirmation_tag(b"session key", CLIENT_CONFIRMATION_LABEL, "alice", &tag));
+        assert!(!verify_confirmation_tag(b"session key", SERVER_CONFIRMATION_LABEL, "alice", &tag));
+        assert!(!verify_confirmation_tag(b"session key", CLIENT_CONFIRMATION_LABEL, "bob", &tag));
+        assert!(!verify_confirmation_tag(b"other key", CLIENT_CONFIRMATION_LABEL, "alice", &tag));
+    }
+}
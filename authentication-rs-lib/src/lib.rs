@@ -1,5 +1,9 @@
+pub mod argon2_profile;
+pub mod cipher_suite;
+pub mod client;
+pub mod key_confirmation;
+pub mod rfc;
 
-use argon2::Params;
 use digest::Digest;
 use generic_array::GenericArray;
 use opaque_ke::{
@@ -9,35 +13,69 @@ use generic_array::typenum::Unsigned;
 
 
 
-pub struct ArgonSlowHash;
+// Argon2id with the parameters of a profile of `argon2_profile::PROFILES`
+pub struct ArgonSlowHash<const PROFILE: u8 = { argon2_profile::LEGACY_PROFILE }>;
 
-impl<D: Hash> SlowHash<D> for ArgonSlowHash {
+impl<D: Hash, const PROFILE: u8> SlowHash<D> for ArgonSlowHash<PROFILE> {
     fn hash(
         input: GenericArray<u8, <D as Digest>::OutputSize>,
     ) -> std::result::Result<Vec<u8>, InternalPakeError> {
+        let profile = argon2_profile::profile(PROFILE).ok_or(InternalPakeError::SlowHashError)?;
         let mut output = vec![0u8; <D as Digest>::OutputSize::USIZE];
-        let params = Params::new(2048, 4, 2, Some(<D as Digest>::OutputSize::USIZE)).unwrap();
-
-        let argon = argon2::Argon2::new(
-            argon2::Algorithm::Argon2id,
-            argon2::Version::V0x13,
-            params
-        );
-        argon
-            .hash_password_into(&input, &[0; argon2::MIN_SALT_LEN], &mut output).unwrap();
-        Ok(output.to_vec())
+        argon2_profile::hash(profile, &input, &mut output).map_err(|_| InternalPakeError::SlowHashError)?;
+        Ok(output)
     }
 }
 
 // The ciphersuite trait allows to specify the underlying primitives
-// that will be used in the OPAQUE protocol
+// that will be used in the OPAQUE protocol. Only the client side depends on the Argon2 profile,
+// messages and server states are the same for every profile.
 #[allow(dead_code)]
-pub struct AuthenticationOpaque;
-impl CipherSuite for AuthenticationOpaque {
+pub struct AuthenticationOpaqueProfile<const PROFILE: u8>;
+impl<const PROFILE: u8> CipherSuite for AuthenticationOpaqueProfile<PROFILE> {
     type Group = curve25519_dalek::ristretto::RistrettoPoint;
     type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
     type Hash = sha2::Sha512;
-    type SlowHash = ArgonSlowHash;
+    type SlowHash = ArgonSlowHash<PROFILE>;
+}
+
+pub type AuthenticationOpaque = AuthenticationOpaqueProfile<{ argon2_profile::LEGACY_PROFILE }>;
+
+// OWASP recommendation for PBKDF2-HMAC-SHA256
+#[cfg(feature = "pbkdf2")]
+const PBKDF2_ITERATIONS: u32 = 600_000;
+#[cfg(feature = "pbkdf2")]
+const PBKDF2_SALT: &[u8] = b"authentication-opaque-pbkdf2";
+
+// PBKDF2-HMAC-SHA256 for `cipher_suite::RISTRETTO255_PBKDF2`
+#[cfg(feature = "pbkdf2")]
+pub struct Pbkdf2SlowHash;
+
+#[cfg(feature = "pbkdf2")]
+impl<D: Hash> SlowHash<D> for Pbkdf2SlowHash {
+    fn hash(
+        input: GenericArray<u8, <D as Digest>::OutputSize>,
+    ) -> std::result::Result<Vec<u8>, InternalPakeError> {
+        let mut output = vec![0u8; <D as Digest>::OutputSize::USIZE];
+        pbkdf2_hash(&input, &mut output);
+        Ok(output)
+    }
+}
+
+#[cfg(feature = "pbkdf2")]
+pub(crate) fn pbkdf2_hash(input: &[u8], output: &mut [u8]) {
+    pbkdf2::pbkdf2::<hmac::Hmac<sha2::Sha256>>(input, PBKDF2_SALT, PBKDF2_ITERATIONS, output);
+}
+
+// Same messages and server states as `AuthenticationOpaque`, only the client slow hash differs
+#[cfg(feature = "pbkdf2")]
+pub struct Pbkdf2Opaque;
+#[cfg(feature = "pbkdf2")]
+impl CipherSuite for Pbkdf2Opaque {
+    type Group = curve25519_dalek::ristretto::RistrettoPoint;
+    type KeyExchange = opaque_ke::key_exchange::tripledh::TripleDH;
+    type Hash = sha2::Sha512;
+    type SlowHash = Pbkdf2SlowHash;
 }
 
 
@@ -0,0 +1,251 @@
+// OPAQUE as finalized in RFC 9807, implemented by opaque-ke 4, for the `cipher_suite` suites with
+// `rfc` set. Unlike opaque-ke 1 the key stretching function is a runtime value, so one suite type
+// serves every Argon2 profile, and the server derives the OPRF keys of every user from a single
+// seed, so registration doesn't keep a server state between its two requests.
+// The messages and states of the flows are passed around as bytes, see `rfc_suite`.
+
+use hkdf::Hkdf;
+use opaque_ke_rfc::{
+    errors::InternalError,
+    generic_array::{ArrayLength, GenericArray},
+    key_exchange::group::Group,
+    ksf::Ksf,
+    rand::rngs::OsRng,
+    ClientLogin, ClientLoginFinishParameters, ClientRegistration, ClientRegistrationFinishParameters, CredentialFinalization, CredentialRequest,
+    CredentialResponse, Identifiers, RegistrationRequest, RegistrationResponse, RegistrationUpload, ServerLogin, ServerLoginParameters,
+    ServerRegistration, ServerSetup,
+};
+
+use crate::{argon2_profile, cipher_suite};
+
+// HKDF info of the server secrets derived from a key of the server key ring
+const OPRF_SEED_INFO: &[u8] = b"authentication-opaque-rfc-oprf-seed";
+const SERVER_KEY_INFO: &[u8] = b"authentication-opaque-rfc-server-key";
+const DUMMY_KEY_INFO: &[u8] = b"authentication-opaque-rfc-dummy-key";
+
+// Key stretching function of a suite, Argon2id with a profile of `argon2_profile::PROFILES` or
+// PBKDF2 for `cipher_suite::RFC_RISTRETTO255_PBKDF2`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStretch {
+    Argon2(u8),
+    #[cfg(feature = "pbkdf2")]
+    Pbkdf2,
+}
+
+impl KeyStretch {
+    // The profile is ignored by suites that don't hash with Argon2
+    pub fn new(suite: &cipher_suite::CipherSuiteInfo, profile: u8) -> Result<Self, String> {
+        if suite.argon2 {
+            return Ok(KeyStretch::Argon2(profile));
+        }
+        #[cfg(feature = "pbkdf2")]
+        if suite.id == cipher_suite::RFC_RISTRETTO255_PBKDF2 {
+            return Ok(KeyStretch::Pbkdf2);
+        }
+        Err(format!("No key stretching function for cipher suite {}", suite.name))
+    }
+}
+
+impl Default for KeyStretch {
+    fn default() -> Self {
+        KeyStretch::Argon2(argon2_profile::DEFAULT_PROFILE)
+    }
+}
+
+impl Ksf for KeyStretch {
+    fn hash<L: ArrayLength<u8>>(&self, input: GenericArray<u8, L>) -> Result<GenericArray<u8, L>, InternalError> {
+        let mut output = GenericArray::default();
+        match self {
+            KeyStretch::Argon2(profile) => {
+                let profile = argon2_profile::profile(*profile).ok_or(InternalError::KsfError)?;
+                argon2_profile::hash(profile, &input, &mut output).map_err(|_| InternalError::KsfError)?;
+            }
+            #[cfg(feature = "pbkdf2")]
+            KeyStretch::Pbkdf2 => crate::pbkdf2_hash(&input, &mut output),
+        }
+        Ok(output)
+    }
+}
+
+// Both sides of the flows of one RFC suite
+pub trait RfcSuite {
+    // Returns the client state and the request
+    fn client_registration_start(&self, password: &str) -> Result<(Vec<u8>, Vec<u8>), String>;
+    // Returns the registration upload
+    fn client_registration_finish(&self, state: &[u8], password: &str, server_response: &[u8], username: &str, ksf: &KeyStretch) -> Result<Vec<u8>, String>;
+    // Returns the client state and the request
+    fn client_login_start(&self, password: &str) -> Result<(Vec<u8>, Vec<u8>), String>;
+    // Returns the credential finalization and the session key
+    fn client_login_finish(&self, state: &[u8], password: &str, server_response: &[u8], username: &str, ksf: &KeyStretch) -> Result<(Vec<u8>, Vec<u8>), String>;
+
+    // `server_private_key` is the private key of an entry of the server key ring
+    fn server_registration_start(&self, server_private_key: &[u8], request: &[u8], username: &str) -> Result<Vec<u8>, String>;
+    // Returns the password file
+    fn server_registration_finish(&self, upload: &[u8]) -> Result<Vec<u8>, String>;
+    // Without a password file the response is indistinguishable from one for a registered user,
+    // returns the server state and the response
+    fn server_login_start(&self, server_private_key: &[u8], password_file: Option<&[u8]>, request: &[u8], username: &str) -> Result<(Vec<u8>, Vec<u8>), String>;
+    // Returns the session key
+    fn server_login_finish(&self, state: &[u8], finalization: &[u8], username: &str) -> Result<Vec<u8>, String>;
+}
+
+fn identifiers(username: &str) -> Identifiers<'_> {
+    Identifiers { client: Some(username.as_bytes()), server: None }
+}
+
+fn expand<L: ArrayLength<u8>>(hkdf: &Hkdf<sha2_10::Sha512>, info: &[u8]) -> Result<GenericArray<u8, L>, String> {
+    let mut output = GenericArray::default();
+    hkdf.expand(info, &mut output).map_err(|err| err.to_string())?;
+    Ok(output)
+}
+
+macro_rules! impl_rfc_suite {
+    ($suite:ident, $group:ty, $hash:ty) => {
+        impl opaque_ke_rfc::CipherSuite for $suite {
+            type OprfCs = $group;
+            type KeyExchange = opaque_ke_rfc::TripleDh<$group, $hash>;
+            type Ksf = KeyStretch;
+        }
+
+        impl $suite {
+            // The OPRF seed, the server key pair and the client key of the fake password file are
+            // all derived from the key ring entry, so they change with it. The fake key also
+            // depends on the username, like the OPRF key, so an unknown username gets a fake
+            // record of its own that stays the same between logins. Real logins derive it too,
+            // keeping the work the same whether the password file exists or not.
+            fn server_setup(server_private_key: &[u8], username: &str) -> Result<ServerSetup<$suite>, String> {
+                let hkdf = Hkdf::<sha2_10::Sha512>::new(None, server_private_key);
+                let oprf_seed = expand::<<$hash as sha2_10::digest::OutputSizeUser>::OutputSize>(&hkdf, OPRF_SEED_INFO)?;
+                let server_key = <$group as Group>::derive_scalar(expand(&hkdf, SERVER_KEY_INFO)?).map_err(|err| err.to_string())?;
+                let dummy_key = <$group as Group>::derive_scalar(expand(&hkdf, &[DUMMY_KEY_INFO, username.as_bytes()].concat())?).map_err(|err| err.to_string())?;
+                let serialized = [
+                    &oprf_seed[..],
+                    &<$group as Group>::serialize_sk(&server_key),
+                    &<$group as Group>::serialize_pk(&<$group as Group>::public_key(&dummy_key)),
+                ].concat();
+                ServerSetup::deserialize(&serialized).map_err(|err| err.to_string())
+            }
+        }
+
+        impl RfcSuite for $suite {
+            fn client_registration_start(&self, password: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+                let result = ClientRegistration::<$suite>::start(&mut OsRng, password.as_bytes()).map_err(|err| err.to_string())?;
+                Ok((result.state.serialize().to_vec(), result.message.serialize().to_vec()))
+            }
+
+            fn client_registration_finish(&self, state: &[u8], password: &str, server_response: &[u8], username: &str, ksf: &KeyStretch) -> Result<Vec<u8>, String> {
+                let state = ClientRegistration::<$suite>::deserialize(state).map_err(|err| err.to_string())?;
+                let result = state.finish(
+                    &mut OsRng,
+                    password.as_bytes(),
+                    RegistrationResponse::deserialize(server_response).map_err(|err| err.to_string())?,
+                    ClientRegistrationFinishParameters::new(identifiers(username), Some(ksf)),
+                ).map_err(|err| err.to_string())?;
+                Ok(result.message.serialize().to_vec())
+            }
+
+            fn client_login_start(&self, password: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+                let result = ClientLogin::<$suite>::start(&mut OsRng, password.as_bytes()).map_err(|err| err.to_string())?;
+                Ok((result.state.serialize().to_vec(), result.message.serialize().to_vec()))
+            }
+
+            fn client_login_finish(&self, state: &[u8], password: &str, server_response: &[u8], username: &str, ksf: &KeyStretch) -> Result<(Vec<u8>, Vec<u8>), String> {
+                let state = ClientLogin::<$suite>::deserialize(state).map_err(|err| err.to_string())?;
+                let result = state.finish(
+                    &mut OsRng,
+                    password.as_bytes(),
+                    CredentialResponse::deserialize(server_response).map_err(|err| err.to_string())?,
+                    ClientLoginFinishParameters::new(None, identifiers(username), Some(ksf)),
+                ).map_err(|err| err.to_string())?;
+                Ok((result.message.serialize().to_vec(), result.session_key.to_vec()))
+            }
+
+            fn server_registration_start(&self, server_private_key: &[u8], request: &[u8], username: &str) -> Result<Vec<u8>, String> {
+                let result = ServerRegistration::<$suite>::start(
+                    &$suite::server_setup(server_private_key, username)?,
+                    RegistrationRequest::deserialize(request).map_err(|err| err.to_string())?,
+                    username.as_bytes(),
+                ).map_err(|err| err.to_string())?;
+                Ok(result.message.serialize().to_vec())
+            }
+
+            fn server_registration_finish(&self, upload: &[u8]) -> Result<Vec<u8>, String> {
+                let upload = RegistrationUpload::<$suite>::deserialize(upload).map_err(|err| err.to_string())?;
+                Ok(ServerRegistration::finish(upload).serialize().to_vec())
+            }
+
+            fn server_login_start(&self, server_private_key: &[u8], password_file: Option<&[u8]>, request: &[u8], username: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+                let password_file = password_file
+                    .map(ServerRegistration::<$suite>::deserialize)
+                    .transpose()
+                    .map_err(|err| err.to_string())?;
+                let result = ServerLogin::start(
+                    &mut OsRng,
+                    &$suite::server_setup(server_private_key, username)?,
+                    password_file,
+                    CredentialRequest::deserialize(request).map_err(|err| err.to_string())?,
+                    username.as_bytes(),
+                    ServerLoginParameters { context: None, identifiers: identifiers(username) },
+                ).map_err(|err| err.to_string())?;
+                Ok((result.state.serialize().to_vec(), result.message.serialize().to_vec()))
+            }
+
+            fn server_login_finish(&self, state: &[u8], finalization: &[u8], username: &str) -> Result<Vec<u8>, String> {
+                let state = ServerLogin::<$suite>::deserialize(state).map_err(|err| err.to_string())?;
+                let result = state.finish(
+                    CredentialFinalization::deserialize(finalization).map_err(|err| err.to_string())?,
+                    ServerLoginParameters { context: None, identifiers: identifiers(username) },
+                ).map_err(|err| err.to_string())?;
+                Ok(result.session_key.to_vec())
+            }
+        }
+    };
+}
+
+// Ristretto255 with SHA-512, for both `RFC_RISTRETTO255_ARGON2` and `RFC_RISTRETTO255_PBKDF2`
+pub struct RfcRistretto255;
+impl_rfc_suite!(RfcRistretto255, opaque_ke_rfc::Ristretto255, sha2_10::Sha512);
+
+// P-256 with SHA-256 for `RFC_P256_ARGON2`
+#[cfg(feature = "p256")]
+pub struct RfcP256;
+#[cfg(feature = "p256")]
+impl_rfc_suite!(RfcP256, p256::NistP256, sha2_10::Sha256);
+
+// `None` for legacy suites and suites not compiled in
+#[must_use]
+pub fn rfc_suite(suite: u8) -> Option<&'static dyn RfcSuite> {
+    match suite {
+        cipher_suite::RFC_RISTRETTO255_ARGON2 => Some(&RfcRistretto255),
+        #[cfg(feature = "pbkdf2")]
+        cipher_suite::RFC_RISTRETTO255_PBKDF2 => Some(&RfcRistretto255),
+        #[cfg(feature = "p256")]
+        cipher_suite::RFC_P256_ARGON2 => Some(&RfcP256),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rfc_suite, KeyStretch, RfcRistretto255};
+    use crate::cipher_suite::{self, RFC_RISTRETTO255_ARGON2};
+
+    #[test]
+    fn fake_login_for_unknown_user_fails_on_finish() {
+        let suite = rfc_suite(RFC_RISTRETTO255_ARGON2).unwrap();
+        let ksf = KeyStretch::new(cipher_suite::suite(RFC_RISTRETTO255_ARGON2).unwrap(), 0).unwrap();
+        let server_key = [3; 32];
+
+        let (client_state, request) = suite.client_login_start("password").unwrap();
+        let (_, response) = suite.server_login_start(&server_key, None, &request, "mallory").unwrap();
+        assert!(suite.client_login_finish(&client_state, "password", &response, "mallory", &ksf).is_err());
+    }
+
+    #[test]
+    fn fake_record_is_stable_per_username() {
+        let server_key = [3; 32];
+        let alice = RfcRistretto255::server_setup(&server_key, "alice").unwrap().serialize();
+        assert_eq!(alice, RfcRistretto255::server_setup(&server_key, "alice").unwrap().serialize());
+        assert_ne!(alice, RfcRistretto255::server_setup(&server_key, "bob").unwrap().serialize());
+    }
+}
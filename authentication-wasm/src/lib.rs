@@ -2,6 +2,22 @@ mod utils;
 pub mod register;
 pub mod login;
 
+use wasm_bindgen::prelude::*;
+
+// Version of the backend API the request bodies of `authentication-api` belong to, sent in the
+// header named by `apiVersionHeader`
+#[must_use]
+#[wasm_bindgen(js_name=apiVersion)]
+pub fn api_version() -> u16 {
+    authentication_api::API_VERSION
+}
+
+#[must_use]
+#[wasm_bindgen(js_name=apiVersionHeader)]
+pub fn api_version_header() -> String {
+    authentication_api::API_VERSION_HEADER.to_string()
+}
+
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
 #[cfg(feature = "wee_alloc")]